@@ -0,0 +1,269 @@
+//! Discovery of ACPI tables, and parsing of the MADT.
+//!
+//! The RSDP comes from the Multiboot2 information. Tables outside the
+//! identity mapped first GiB are identity mapped read-only on demand.
+
+use crate::memory::paging::{self, Flags};
+use crate::multiboot::BootInfo;
+use crate::util::bytes::{checksum_ok, read};
+use alloc::vec::Vec;
+
+/// Size of the header every system description table starts with
+const HEADER_SIZE: usize = 36;
+
+/// The common header of all system description tables
+#[derive(Clone, Copy, Debug)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+}
+
+impl SdtHeader {
+    fn parse(bytes: &[u8]) -> Option<SdtHeader> {
+        Some(SdtHeader {
+            signature: read(bytes, 0)?,
+            length: read(bytes, 4)?,
+            revision: read(bytes, 8)?,
+            oem_id: read(bytes, 10)?,
+            oem_table_id: read(bytes, 16)?,
+            oem_revision: read(bytes, 24)?,
+        })
+    }
+}
+
+/// Maps the table at `phys` and returns it, if its checksum is valid
+fn map_table(phys: usize) -> Option<&'static [u8]> {
+    let mut mapper = paging::mapper().lock();
+    mapper
+        .identity_map(phys, HEADER_SIZE, Flags::PRESENT)
+        .ok()?;
+    let header = unsafe { core::slice::from_raw_parts(phys as *const u8, HEADER_SIZE) };
+    let length = SdtHeader::parse(header)?.length as usize;
+    if length < HEADER_SIZE {
+        return None;
+    }
+    mapper.identity_map(phys, length, Flags::PRESENT).ok()?;
+    let table = unsafe { core::slice::from_raw_parts(phys as *const u8, length) };
+    if checksum_ok(table) {
+        Some(table)
+    } else {
+        None
+    }
+}
+
+static TABLES: spin::Once<Vec<&'static [u8]>> = spin::Once::new();
+
+/// Collects all tables listed in the RSDT or XSDT.
+/// Returns `false` if the bootloader didn't pass an RSDP or the root table is broken.
+pub fn init(info: &BootInfo) -> bool {
+    let rsdp = match info.rsdp() {
+        Some(rsdp) => rsdp,
+        None => {
+            log::warn!("No ACPI RSDP was passed by the bootloader");
+            return false;
+        }
+    };
+    // the XSDT has 64-bit entries, the RSDT 32-bit ones
+    let (root, entry_size) = match rsdp.xsdt_address {
+        Some(xsdt) => (map_table(xsdt as usize), 8),
+        None => (map_table(rsdp.rsdt_address as usize), 4),
+    };
+    let root = match root {
+        Some(root) => root,
+        None => {
+            log::warn!("The ACPI root table is invalid");
+            return false;
+        }
+    };
+
+    let mut tables = Vec::new();
+    for offset in (HEADER_SIZE..root.len()).step_by(entry_size) {
+        let address = if entry_size == 8 {
+            read::<u64>(root, offset).map(|a| a as usize)
+        } else {
+            read::<u32>(root, offset).map(|a| a as usize)
+        };
+        match address.and_then(map_table) {
+            Some(table) => {
+                log::debug!(
+                    "ACPI table {} at 0x{:x}",
+                    core::str::from_utf8(&table[..4]).unwrap_or("????"),
+                    table.as_ptr() as usize
+                );
+                tables.push(table);
+            }
+            None => log::warn!("Skipping an invalid ACPI table at {:x?}", address),
+        }
+    }
+    log::info!(
+        "Found {} ACPI tables, revision {} from {}",
+        tables.len(),
+        rsdp.revision,
+        core::str::from_utf8(&rsdp.oem_id).unwrap_or("an unknown OEM")
+    );
+    TABLES.call_once(|| tables);
+    true
+}
+
+/// The first table with the given signature, including its header
+pub fn find(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    TABLES.get()?.iter().copied().find(|t| &t[..4] == signature)
+}
+
+/// A processor's local APIC
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApic {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    /// Whether the processor is usable
+    pub enabled: bool,
+}
+
+/// An I/O APIC
+#[derive(Clone, Copy, Debug)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    /// The first global system interrupt handled by this I/O APIC
+    pub gsi_base: u32,
+}
+
+/// Pin polarity of an interrupt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// Trigger mode of an interrupt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// Decodes MPS INTI flags, `default` is used for "conforms to the bus"
+fn inti_flags(flags: u16, default: (Polarity, TriggerMode)) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b01 => Polarity::ActiveHigh,
+        0b11 => Polarity::ActiveLow,
+        _ => default.0,
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b01 => TriggerMode::Edge,
+        0b11 => TriggerMode::Level,
+        _ => default.1,
+    };
+    (polarity, trigger)
+}
+
+/// ISA interrupts are active high and edge triggered
+const ISA_DEFAULT: (Polarity, TriggerMode) = (Polarity::ActiveHigh, TriggerMode::Edge);
+
+/// Remaps an ISA IRQ to a different global system interrupt
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptOverride {
+    /// The ISA IRQ line
+    pub source: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+/// A local APIC LINT pin connected to the NMI line
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApicNmi {
+    /// ACPI processor ID, 0xFF means all processors
+    pub processor_id: u8,
+    /// LINT0 or LINT1
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+/// The multiple APIC description table
+#[derive(Clone, Debug)]
+pub struct Madt {
+    /// Physical address of the local APICs
+    pub local_apic_address: u64,
+    /// Whether the system also has dual 8259 PICs
+    pub pcat_compat: bool,
+    pub local_apics: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    /// Parses the MADT, unknown entries are skipped
+    pub fn parse(table: &[u8]) -> Option<Madt> {
+        let mut madt = Madt {
+            local_apic_address: read::<u32>(table, HEADER_SIZE)? as u64,
+            pcat_compat: read::<u32>(table, HEADER_SIZE + 4)? & 1 != 0,
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+        let mut offset = HEADER_SIZE + 8;
+        while let (Some(typ), Some(length)) =
+            (read::<u8>(table, offset), read::<u8>(table, offset + 1))
+        {
+            let entry = table.get(offset..offset + length as usize)?;
+            match typ {
+                0 => madt.local_apics.push(LocalApic {
+                    processor_id: read(entry, 2)?,
+                    apic_id: read(entry, 3)?,
+                    enabled: read::<u32>(entry, 4)? & 1 != 0,
+                }),
+                1 => madt.io_apics.push(IoApic {
+                    id: read(entry, 2)?,
+                    address: read(entry, 4)?,
+                    gsi_base: read(entry, 8)?,
+                }),
+                2 => {
+                    let (polarity, trigger) = inti_flags(read(entry, 8)?, ISA_DEFAULT);
+                    madt.overrides.push(InterruptOverride {
+                        source: read(entry, 3)?,
+                        gsi: read(entry, 4)?,
+                        polarity,
+                        trigger,
+                    })
+                }
+                4 => {
+                    let (polarity, trigger) = inti_flags(read(entry, 3)?, ISA_DEFAULT);
+                    madt.nmis.push(LocalApicNmi {
+                        processor_id: read(entry, 2)?,
+                        lint: read(entry, 5)?,
+                        polarity,
+                        trigger,
+                    })
+                }
+                5 => madt.local_apic_address = read(entry, 4)?,
+                _ => {}
+            }
+            if length < 2 {
+                break;
+            }
+            offset += length as usize;
+        }
+        Some(madt)
+    }
+
+    /// The global system interrupt, polarity and trigger mode an ISA IRQ is connected to
+    pub fn isa_irq(&self, line: u8) -> (u32, Polarity, TriggerMode) {
+        match self.overrides.iter().find(|o| o.source == line) {
+            Some(o) => (o.gsi, o.polarity, o.trigger),
+            None => (line as u32, ISA_DEFAULT.0, ISA_DEFAULT.1),
+        }
+    }
+}
+
+/// The parsed MADT, if there is one
+pub fn madt() -> Option<Madt> {
+    Madt::parse(find(b"APIC")?)
+}
@@ -1,15 +1,20 @@
 global start
+global p4_table, p3_table, p2_table
+global stack_guard, stack_bottom, stack_top
 extern long_mode_start
 
 section .text
 bits 32
 start:
     mov     esp, stack_top
-    
-    push    eax
+
+    ; the cpuid checks clobber eax and ebx, keep the
+    ; multiboot magic and info pointer for kmain
+    mov     edi, eax
+    mov     esi, ebx
+
     call    screen_clear
-    pop     eax 
-    
+
     call    check_multiboot
     call    check_cpuid
     call    check_long_mode
@@ -27,7 +32,7 @@ start:
     hlt
 
 check_multiboot:
-    cmp     eax, 0x36d76289
+    cmp     edi, 0x36d76289
     jne     .no_multiboot
     ret
 .no_multiboot:
@@ -188,7 +193,9 @@ p4_table:       resb        4096
 p3_table:       resb        4096
 p2_table:       resb        4096
 
-stack_bottom:   resb        4096 * 4    
+; unmapped by the kernel, so an overflow faults instead of corrupting p2_table
+stack_guard:    resb        4096
+stack_bottom:   resb        4096 * 4
 stack_top:
 
 
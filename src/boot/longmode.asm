@@ -12,7 +12,10 @@ long_mode_start:
     mov     fs, ax
     mov     gs, ax
 
-    push    rbx
+    ; kmain(multiboot_magic: u32, multiboot_info: usize)
+    ; the upper halves are undefined after leaving protected mode
+    mov     edi, edi
+    mov     esi, esi
     call    kmain
 
     hlt
\ No newline at end of file
@@ -6,22 +6,18 @@ header_start:
     ; checksum
     dd 0x100000000 - (0xe85250d6 + 0 + (header_end - header_start))
 
+    ; framebuffer tag, optional: without a graphics mode the TTY uses VGA text mode
+    align 8, db 0
+    dw 5             ; type
+    dw 1             ; flags, optional
+    dd 20            ; size
+    dd 1280          ; width
+    dd 1024          ; height
+    dd 32            ; depth
 
-    ; MULTIBOOT_MEMORY_INFO
-    dd 0x00000000    ; header_addr 
-    dd 0x00000000    ; load_addr 
-    dd 0x00000000    ; load_end_addr 
-    dd 0x00000000    ; bss_end_addr 
-    dd 0x00000000    ; entry_addr 
-
-    ; MULTIBOOT_VIDEO_MODE
-    dd 0x00000000    ; mode_type 
-    dd 1280          ; width 
-    dd 1024          ; height 
-    dd 32            ; depth 
-
-    ; required end tag
-;    dw 0    ; type
-;    dw 0    ; flags
-;    dd 8    ; size
-header_end:
\ No newline at end of file
+    ; required end tag, tags are 8 byte aligned
+    align 8, db 0
+    dw 0    ; type
+    dw 0    ; flags
+    dd 8    ; size
+header_end:
@@ -0,0 +1,155 @@
+//! Thin wrappers around privileged x86_64 instructions and control registers
+
+/// The result of a `cpuid` invocation
+#[derive(Clone, Copy, Debug)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Executes `cpuid` with the given leaf and subleaf
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+    unsafe {
+        // rbx is reserved by LLVM, so it has to be saved manually
+        asm!("
+            mov {0:r}, rbx
+            cpuid
+            xchg {0:r}, rbx
+        ", out(reg) ebx, inout("eax") leaf => eax, inout("ecx") subleaf => ecx, out("edx") edx);
+    }
+    CpuidResult { eax, ebx, ecx, edx }
+}
+
+/// The highest supported extended `cpuid` leaf
+pub fn cpuid_max_extended() -> u32 {
+    cpuid(0x8000_0000, 0).eax
+}
+
+/// Whether the no-execute page protection bit is supported
+pub fn has_nx() -> bool {
+    cpuid_max_extended() >= 0x8000_0001 && cpuid(0x8000_0001, 0).edx & (1 << 20) != 0
+}
+
+/// Whether 1GiB pages are supported
+pub fn has_1gib_pages() -> bool {
+    cpuid_max_extended() >= 0x8000_0001 && cpuid(0x8000_0001, 0).edx & (1 << 26) != 0
+}
+
+/// Whether the time stamp counter runs at a constant rate in all power states
+pub fn has_invariant_tsc() -> bool {
+    cpuid_max_extended() >= 0x8000_0007 && cpuid(0x8000_0007, 0).edx & (1 << 8) != 0
+}
+
+/// The extended feature enable register
+pub const MSR_EFER: u32 = 0xC000_0080;
+/// EFER bit enabling the no-execute page bit
+pub const EFER_NXE: u64 = 1 << 11;
+
+/// Reads a model specific register
+/// # Safety
+/// The MSR must exist, otherwise a general protection fault is raised.
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high);
+    (high as u64) << 32 | low as u64
+}
+
+/// Writes a model specific register
+/// # Safety
+/// The MSR must exist and the value must be valid for it.
+pub unsafe fn wrmsr(msr: u32, val: u64) {
+    asm!("wrmsr", in("ecx") msr, in("eax") val as u32, in("edx") (val >> 32) as u32);
+}
+
+/// The physical address of the active P4 table, plus flags
+pub fn read_cr3() -> u64 {
+    let out: u64;
+    unsafe { asm!("mov {}, cr3", out(reg) out) };
+    out
+}
+
+/// Switches the active P4 table, flushing all non global TLB entries
+/// # Safety
+/// `val` must point to a valid P4 table which maps the running kernel.
+pub unsafe fn write_cr3(val: u64) {
+    asm!("mov cr3, {}", in(reg) val);
+}
+
+/// The address of the last page fault
+pub fn read_cr2() -> u64 {
+    let out: u64;
+    unsafe { asm!("mov {}, cr2", out(reg) out) };
+    out
+}
+
+/// Invalidates the TLB entry of the page containing `addr`
+pub fn invlpg(addr: usize) {
+    unsafe { asm!("invlpg [{}]", in(reg) addr) };
+}
+
+/// Reads the time stamp counter, the cycles since reset
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe { asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack)) };
+    (high as u64) << 32 | low as u64
+}
+
+/// Halts the CPU until the next interrupt arrives
+pub fn hlt() {
+    unsafe { asm!("hlt", options(nomem, nostack)) };
+}
+
+/// Disables maskable interrupts
+pub fn disable_interrupts() {
+    unsafe { asm!("cli", options(nomem, nostack)) };
+}
+
+/// Enables maskable interrupts
+pub fn enable_interrupts() {
+    unsafe { asm!("sti", options(nomem, nostack)) };
+}
+
+/// Enables interrupts and halts until the next one arrives.
+/// No interrupt can be handled between the two instructions.
+pub fn enable_interrupts_and_hlt() {
+    unsafe { asm!("sti; hlt", options(nomem, nostack)) };
+}
+
+/// Disables interrupts for good and halts, for unrecoverable errors
+pub fn halt_forever() -> ! {
+    loop {
+        disable_interrupts();
+        hlt();
+    }
+}
+
+/// The current code segment selector
+pub fn read_cs() -> u16 {
+    let out: u16;
+    unsafe { asm!("mov {:x}, cs", out(reg) out, options(nomem, nostack)) };
+    out
+}
+
+/// Whether maskable interrupts are enabled
+pub fn interrupts_enabled() -> bool {
+    let rflags: u64;
+    unsafe { asm!("pushfq; pop {}", out(reg) rflags, options(nomem, preserves_flags)) };
+    rflags & (1 << 9) != 0
+}
+
+/// Runs `f` with interrupts disabled, restoring the previous state afterwards.
+/// Use this around locks which are also taken by interrupt handlers.
+pub fn without_interrupts<F: FnOnce() -> R, R>(f: F) -> R {
+    let enabled = interrupts_enabled();
+    if enabled {
+        disable_interrupts();
+    }
+    let result = f();
+    if enabled {
+        enable_interrupts();
+    }
+    result
+}
@@ -0,0 +1,159 @@
+//! The global descriptor table and the task state segment.
+//!
+//! Replaces the two entry GDT from `boot.asm` with kernel and user code and
+//! data segments and a TSS, whose interrupt stack table gives the double
+//! fault, NMI and machine check handlers their own known-good stacks.
+
+use core::ptr::addr_of;
+
+/// Kernel code segment selector, the same as in the boot GDT
+pub const KERNEL_CODE: u16 = 0x08;
+/// Kernel data segment selector
+pub const KERNEL_DATA: u16 = 0x10;
+/// User data segment selector, with RPL 3
+pub const USER_DATA: u16 = 0x18 | 3;
+/// User code segment selector, with RPL 3
+pub const USER_CODE: u16 = 0x20 | 3;
+/// Task state segment selector
+pub const TSS: u16 = 0x28;
+
+/// IST index of the double fault stack
+pub const DOUBLE_FAULT_IST: u8 = 1;
+/// IST index of the non-maskable interrupt stack
+pub const NMI_IST: u8 = 2;
+/// IST index of the machine check stack
+pub const MACHINE_CHECK_IST: u8 = 3;
+
+const STACK_SIZE: usize = 4096 * 4;
+
+#[repr(C, align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+static mut PRIVILEGE_STACK: Stack = Stack([0; STACK_SIZE]);
+static mut DOUBLE_FAULT_STACK: Stack = Stack([0; STACK_SIZE]);
+static mut NMI_STACK: Stack = Stack([0; STACK_SIZE]);
+static mut MACHINE_CHECK_STACK: Stack = Stack([0; STACK_SIZE]);
+
+/// The top of a stack, which grows downwards
+fn stack_top(stack: *const Stack) -> u64 {
+    stack as u64 + STACK_SIZE as u64
+}
+
+/// The 64-bit task state segment
+#[repr(C, packed(4))]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    /// stacks loaded on a privilege change to ring 0-2
+    privilege_stacks: [u64; 3],
+    reserved_2: u64,
+    /// stacks loaded for IDT entries with a non-zero IST index
+    interrupt_stacks: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    iomap_base: u16,
+}
+
+static_assertions::assert_eq_size!(TaskStateSegment, [u8; 104]);
+
+// Segment descriptor bits
+const ACCESSED: u64 = 1 << 40;
+const WRITABLE: u64 = 1 << 41;
+const EXECUTABLE: u64 = 1 << 43;
+const USER_SEGMENT: u64 = 1 << 44;
+const DPL_3: u64 = 3 << 45;
+const PRESENT: u64 = 1 << 47;
+const LONG_MODE: u64 = 1 << 53;
+const DEFAULT_SIZE: u64 = 1 << 54;
+const GRANULARITY: u64 = 1 << 55;
+const MAX_LIMIT: u64 = 0xFFFF | (0xF << 48);
+
+const COMMON: u64 = USER_SEGMENT | PRESENT | WRITABLE | ACCESSED | MAX_LIMIT | GRANULARITY;
+const KERNEL_CODE_SEGMENT: u64 = COMMON | EXECUTABLE | LONG_MODE;
+const KERNEL_DATA_SEGMENT: u64 = COMMON | DEFAULT_SIZE;
+const USER_CODE_SEGMENT: u64 = KERNEL_CODE_SEGMENT | DPL_3;
+const USER_DATA_SEGMENT: u64 = KERNEL_DATA_SEGMENT | DPL_3;
+
+/// The two descriptor halves of an available 64-bit TSS
+fn tss_descriptor(tss: &'static TaskStateSegment) -> (u64, u64) {
+    let base = tss as *const _ as u64;
+    let limit = (core::mem::size_of::<TaskStateSegment>() - 1) as u64;
+    let low = PRESENT
+        | (0x9 << 40)
+        | (limit & 0xFFFF)
+        | ((base & 0xFF_FFFF) << 16)
+        | (((base >> 24) & 0xFF) << 56);
+    (low, base >> 32)
+}
+
+#[repr(C, align(8))]
+struct Gdt([u64; 7]);
+
+#[repr(C, packed)]
+struct DescriptorPointer {
+    limit: u16,
+    base: u64,
+}
+
+static TSS_INSTANCE: spin::Once<TaskStateSegment> = spin::Once::new();
+static GDT: spin::Once<Gdt> = spin::Once::new();
+
+/// Loads the GDT, reloads all segment registers and loads the TSS
+pub fn init() {
+    let tss = TSS_INSTANCE.call_once(|| unsafe {
+        TaskStateSegment {
+            reserved_1: 0,
+            privilege_stacks: [stack_top(addr_of!(PRIVILEGE_STACK)), 0, 0],
+            reserved_2: 0,
+            interrupt_stacks: [
+                stack_top(addr_of!(DOUBLE_FAULT_STACK)),
+                stack_top(addr_of!(NMI_STACK)),
+                stack_top(addr_of!(MACHINE_CHECK_STACK)),
+                0,
+                0,
+                0,
+                0,
+            ],
+            reserved_3: 0,
+            reserved_4: 0,
+            // no I/O permission bitmap
+            iomap_base: core::mem::size_of::<TaskStateSegment>() as u16,
+        }
+    });
+    let (tss_low, tss_high) = tss_descriptor(tss);
+    let gdt = GDT.call_once(|| {
+        Gdt([
+            0,
+            KERNEL_CODE_SEGMENT,
+            KERNEL_DATA_SEGMENT,
+            USER_DATA_SEGMENT,
+            USER_CODE_SEGMENT,
+            tss_low,
+            tss_high,
+        ])
+    });
+
+    let pointer = DescriptorPointer {
+        limit: (core::mem::size_of::<Gdt>() - 1) as u16,
+        base: gdt as *const Gdt as u64,
+    };
+    unsafe {
+        asm!("lgdt [{}]", in(reg) &pointer, options(readonly, nostack));
+        // far return to reload cs
+        asm!("
+            push {sel}
+            lea {tmp}, [rip + 2f]
+            push {tmp}
+            retfq
+        2:
+        ", sel = in(reg) KERNEL_CODE as u64, tmp = lateout(reg) _);
+        asm!("
+            mov ds, {0:x}
+            mov es, {0:x}
+            mov fs, {0:x}
+            mov gs, {0:x}
+            mov ss, {0:x}
+        ", in(reg) KERNEL_DATA, options(nostack));
+        asm!("ltr {0:x}", in(reg) TSS, options(nostack));
+    }
+    log::info!("Loaded the GDT and TSS");
+}
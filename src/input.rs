@@ -0,0 +1,272 @@
+//! Keyboard input events and the queue they wait in.
+//!
+//! Drivers `push` events from their interrupt handlers, the TTY side takes
+//! them out with `pop` or `wait` and turn them into characters with
+//! `keymap::translate`. Terminals on a serial line send characters rather
+//! than keys, those are queued with `push_char` and belong to the console
+//! mirrored to the line, see `tty::attach_serial`.
+
+pub mod keymap;
+
+use crate::cpu;
+use crate::util::ring::RingBuffer;
+
+/// A key, named after its position on a US keyboard.
+/// Layouts decide which character a key produces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCode {
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+
+    Backtick,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    Num7,
+    Num8,
+    Num9,
+    Num0,
+    Minus,
+    Equals,
+    Backspace,
+
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+
+    CapsLock,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Quote,
+    Enter,
+
+    LeftShift,
+    /// The extra key next to left shift on ISO keyboards
+    Iso102,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+
+    LeftCtrl,
+    LeftSuper,
+    LeftAlt,
+    Space,
+    /// Right alt, AltGr on most non-US layouts
+    RightAlt,
+    RightSuper,
+    Menu,
+    RightCtrl,
+
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+
+    NumLock,
+    KeypadSlash,
+    KeypadStar,
+    KeypadMinus,
+    KeypadPlus,
+    KeypadEnter,
+    KeypadPeriod,
+    Keypad0,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+}
+
+/// Held modifier keys and active lock keys
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers(u16);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const LEFT_SHIFT: Modifiers = Modifiers(1 << 0);
+    pub const RIGHT_SHIFT: Modifiers = Modifiers(1 << 1);
+    pub const LEFT_CTRL: Modifiers = Modifiers(1 << 2);
+    pub const RIGHT_CTRL: Modifiers = Modifiers(1 << 3);
+    pub const ALT: Modifiers = Modifiers(1 << 4);
+    pub const ALT_GR: Modifiers = Modifiers(1 << 5);
+    pub const CAPS_LOCK: Modifiers = Modifiers(1 << 6);
+    pub const NUM_LOCK: Modifiers = Modifiers(1 << 7);
+    pub const SCROLL_LOCK: Modifiers = Modifiers(1 << 8);
+
+    /// The raw bits
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Whether all modifiers of `other` are set
+    pub fn contains(&self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Sets or clears the modifiers of `other`
+    pub fn set(&mut self, other: Modifiers, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+
+    /// Flips the modifiers of `other`
+    pub fn toggle(&mut self, other: Modifiers) {
+        self.0 ^= other.0;
+    }
+
+    /// Whether either shift key is held
+    pub fn shift(&self) -> bool {
+        self.0 & (Self::LEFT_SHIFT.0 | Self::RIGHT_SHIFT.0) != 0
+    }
+
+    /// Whether either ctrl key is held
+    pub fn ctrl(&self) -> bool {
+        self.0 & (Self::LEFT_CTRL.0 | Self::RIGHT_CTRL.0) != 0
+    }
+
+    /// Whether the left alt key is held
+    pub fn alt(&self) -> bool {
+        self.contains(Self::ALT)
+    }
+
+    /// Whether AltGr is held
+    pub fn alt_gr(&self) -> bool {
+        self.contains(Self::ALT_GR)
+    }
+
+    pub fn caps_lock(&self) -> bool {
+        self.contains(Self::CAPS_LOCK)
+    }
+
+    pub fn num_lock(&self) -> bool {
+        self.contains(Self::NUM_LOCK)
+    }
+
+    pub fn scroll_lock(&self) -> bool {
+        self.contains(Self::SCROLL_LOCK)
+    }
+}
+
+impl core::ops::BitOr for Modifiers {
+    type Output = Modifiers;
+    fn bitor(self, rhs: Modifiers) -> Modifiers {
+        Modifiers(self.0 | rhs.0)
+    }
+}
+
+/// A key being pressed or released
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    /// `false` on release, repeated presses come from typematic repeat
+    pub pressed: bool,
+    /// The modifiers after the event was applied
+    pub modifiers: Modifiers,
+}
+
+/// Something typed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Key(KeyEvent),
+    /// A character typed on the terminal of `tty::attach_serial`, as a CP437
+    /// byte like `keymap::translate` gives. It goes to `tty::tty()`, not to the
+    /// console on screen.
+    Char(u8),
+}
+
+const QUEUE_SIZE: usize = 128;
+
+static QUEUE: spin::Mutex<RingBuffer<Event, QUEUE_SIZE>> = spin::Mutex::new(RingBuffer::new());
+
+fn push_event(event: Event) {
+    cpu::without_interrupts(|| {
+        if QUEUE.lock().push(event).is_err() {
+            log::warn!("Input queue full, dropping {:?}", event);
+        }
+    });
+}
+
+/// Queues `event`, dropping it if the queue is full
+pub fn push(event: KeyEvent) {
+    push_event(Event::Key(event));
+}
+
+/// Queues a typed character, dropping it if the queue is full
+pub fn push_char(byte: u8) {
+    push_event(Event::Char(byte));
+}
+
+/// Takes the oldest queued event
+pub fn pop() -> Option<Event> {
+    cpu::without_interrupts(|| QUEUE.lock().pop())
+}
+
+/// Halts until an event arrives and takes it. Needs interrupts enabled.
+pub fn wait() -> Event {
+    loop {
+        cpu::disable_interrupts();
+        if let Some(event) = QUEUE.lock().pop() {
+            cpu::enable_interrupts();
+            return event;
+        }
+        // sti only takes effect after hlt, so an event can't slip in between
+        cpu::enable_interrupts_and_hlt();
+    }
+}
@@ -0,0 +1,458 @@
+//! Keyboard layouts, turning key events into the CP437 bytes the TTY stores.
+//!
+//! Besides US, the Czech and Slovak QWERTZ layouts are supported, with their
+//! AltGr level and dead keys. A dead key (e.g. the háček on shift + `=`)
+//! produces nothing by itself, it modifies the next letter instead: háček
+//! then "c" gives "č". A dead key followed by space gives the accent itself.
+//! The layout is chosen with the `kbd=` command line option and switched with
+//! Alt+F12.
+//!
+//! CP437 has no glyph for many Czech and Slovak letters. Unless the font has
+//! them (see `cp437::set_czech`), those are replaced with the letter without
+//! its diacritic (see `cp437::base_letter`), so "ř" and "ů" are typed as "r"
+//! and "u". Accents without a glyph of their own fall back to the closest
+//! ASCII character, like "'" for the čárka.
+
+use super::{KeyCode, KeyEvent};
+use crate::tty::cp437;
+use arrayvec::ArrayVec;
+
+/// A keyboard layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    /// Czech QWERTZ
+    Cz,
+    /// Slovak QWERTZ
+    Sk,
+}
+
+impl Layout {
+    /// The layout with the given name, "us", "cz" or "sk"
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "us" => Some(Layout::Us),
+            "cz" => Some(Layout::Cz),
+            "sk" => Some(Layout::Sk),
+            _ => None,
+        }
+    }
+
+    /// The layout after this one, wrapping around to the first
+    pub fn next(self) -> Layout {
+        match self {
+            Layout::Us => Layout::Cz,
+            Layout::Cz => Layout::Sk,
+            Layout::Sk => Layout::Us,
+        }
+    }
+}
+
+/// An accent typed before the letter it goes on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeadKey {
+    /// čárka, as in "á"
+    Acute,
+    /// háček, as in "č"
+    Caron,
+    /// as in "ä"
+    Diaeresis,
+    /// vokáň, as in "ô"
+    Circumflex,
+    /// kroužek, as in "ů"
+    Ring,
+    /// as in "à"
+    Grave,
+}
+
+impl DeadKey {
+    /// The accent on `letter`, if there is such a letter
+    fn compose(self, letter: char) -> Option<char> {
+        let lower = letter.to_lowercase().next()?;
+        let composed = match (self, lower) {
+            (DeadKey::Acute, 'a') => 'á',
+            (DeadKey::Acute, 'c') => 'ć',
+            (DeadKey::Acute, 'e') => 'é',
+            (DeadKey::Acute, 'i') => 'í',
+            (DeadKey::Acute, 'l') => 'ĺ',
+            (DeadKey::Acute, 'n') => 'ń',
+            (DeadKey::Acute, 'o') => 'ó',
+            (DeadKey::Acute, 'r') => 'ŕ',
+            (DeadKey::Acute, 's') => 'ś',
+            (DeadKey::Acute, 'u') => 'ú',
+            (DeadKey::Acute, 'y') => 'ý',
+            (DeadKey::Acute, 'z') => 'ź',
+            (DeadKey::Caron, 'c') => 'č',
+            (DeadKey::Caron, 'd') => 'ď',
+            (DeadKey::Caron, 'e') => 'ě',
+            (DeadKey::Caron, 'l') => 'ľ',
+            (DeadKey::Caron, 'n') => 'ň',
+            (DeadKey::Caron, 'r') => 'ř',
+            (DeadKey::Caron, 's') => 'š',
+            (DeadKey::Caron, 't') => 'ť',
+            (DeadKey::Caron, 'z') => 'ž',
+            (DeadKey::Diaeresis, 'a') => 'ä',
+            (DeadKey::Diaeresis, 'e') => 'ë',
+            (DeadKey::Diaeresis, 'i') => 'ï',
+            (DeadKey::Diaeresis, 'o') => 'ö',
+            (DeadKey::Diaeresis, 'u') => 'ü',
+            (DeadKey::Diaeresis, 'y') => 'ÿ',
+            (DeadKey::Circumflex, 'a') => 'â',
+            (DeadKey::Circumflex, 'e') => 'ê',
+            (DeadKey::Circumflex, 'i') => 'î',
+            (DeadKey::Circumflex, 'o') => 'ô',
+            (DeadKey::Circumflex, 'u') => 'û',
+            (DeadKey::Ring, 'a') => 'å',
+            (DeadKey::Ring, 'u') => 'ů',
+            (DeadKey::Grave, 'a') => 'à',
+            (DeadKey::Grave, 'e') => 'è',
+            (DeadKey::Grave, 'i') => 'ì',
+            (DeadKey::Grave, 'o') => 'ò',
+            (DeadKey::Grave, 'u') => 'ù',
+            _ => return None,
+        };
+        if lower == letter {
+            Some(composed)
+        } else {
+            composed.to_uppercase().next()
+        }
+    }
+
+    /// The byte for the accent typed on its own
+    fn spacing(self) -> u8 {
+        match self {
+            DeadKey::Acute => b'\'',
+            DeadKey::Caron | DeadKey::Circumflex => b'^',
+            DeadKey::Diaeresis => b'"',
+            // the degree sign
+            DeadKey::Ring => 0xF8,
+            DeadKey::Grave => b'`',
+        }
+    }
+}
+
+/// What a key produces on one level
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    None,
+    Char(char),
+    Dead(DeadKey),
+}
+
+use DeadKey::*;
+
+const NO: Key = Key::None;
+
+fn ch(c: char) -> Key {
+    Key::Char(c)
+}
+
+fn dead(d: DeadKey) -> Key {
+    Key::Dead(d)
+}
+
+/// The normal, shift and AltGr levels of a key
+type Levels = [Key; 3];
+
+/// Keys producing the same characters on all layouts
+fn common(code: KeyCode) -> Option<Levels> {
+    use KeyCode::*;
+    Some(match code {
+        Space => [ch(' '), ch(' '), ch(' ')],
+        Enter | KeypadEnter => [ch('\n'), ch('\n'), NO],
+        Tab => [ch('\t'), ch('\t'), NO],
+        Backspace => [ch('\x08'), ch('\x08'), NO],
+        Escape => [ch('\x1b'), ch('\x1b'), NO],
+        KeypadSlash => [ch('/'), ch('/'), NO],
+        KeypadStar => [ch('*'), ch('*'), NO],
+        KeypadMinus => [ch('-'), ch('-'), NO],
+        KeypadPlus => [ch('+'), ch('+'), NO],
+        _ => {
+            let letter = letter(code)?;
+            [ch(letter), ch(letter.to_ascii_uppercase()), NO]
+        }
+    })
+}
+
+fn letter(code: KeyCode) -> Option<char> {
+    use KeyCode::*;
+    Some(match code {
+        A => 'a',
+        B => 'b',
+        C => 'c',
+        D => 'd',
+        E => 'e',
+        F => 'f',
+        G => 'g',
+        H => 'h',
+        I => 'i',
+        J => 'j',
+        K => 'k',
+        L => 'l',
+        M => 'm',
+        N => 'n',
+        O => 'o',
+        P => 'p',
+        Q => 'q',
+        R => 'r',
+        S => 's',
+        T => 't',
+        U => 'u',
+        V => 'v',
+        W => 'w',
+        X => 'x',
+        Y => 'y',
+        Z => 'z',
+        _ => return None,
+    })
+}
+
+/// The digit or the decimal point of a keypad key, which only type with num lock on
+fn keypad(code: KeyCode) -> Option<char> {
+    use KeyCode::*;
+    Some(match code {
+        Keypad0 => '0',
+        Keypad1 => '1',
+        Keypad2 => '2',
+        Keypad3 => '3',
+        Keypad4 => '4',
+        Keypad5 => '5',
+        Keypad6 => '6',
+        Keypad7 => '7',
+        Keypad8 => '8',
+        Keypad9 => '9',
+        KeypadPeriod => '.',
+        _ => return None,
+    })
+}
+
+fn us(code: KeyCode) -> Option<Levels> {
+    use KeyCode::*;
+    Some(match code {
+        Backtick => [ch('`'), ch('~'), NO],
+        Num1 => [ch('1'), ch('!'), NO],
+        Num2 => [ch('2'), ch('@'), NO],
+        Num3 => [ch('3'), ch('#'), NO],
+        Num4 => [ch('4'), ch('$'), NO],
+        Num5 => [ch('5'), ch('%'), NO],
+        Num6 => [ch('6'), ch('^'), NO],
+        Num7 => [ch('7'), ch('&'), NO],
+        Num8 => [ch('8'), ch('*'), NO],
+        Num9 => [ch('9'), ch('('), NO],
+        Num0 => [ch('0'), ch(')'), NO],
+        Minus => [ch('-'), ch('_'), NO],
+        Equals => [ch('='), ch('+'), NO],
+        LeftBracket => [ch('['), ch('{'), NO],
+        RightBracket => [ch(']'), ch('}'), NO],
+        Backslash | Iso102 => [ch('\\'), ch('|'), NO],
+        Semicolon => [ch(';'), ch(':'), NO],
+        Quote => [ch('\''), ch('"'), NO],
+        Comma => [ch(','), ch('<'), NO],
+        Period => [ch('.'), ch('>'), NO],
+        Slash => [ch('/'), ch('?'), NO],
+        _ => return common(code),
+    })
+}
+
+/// Keys which Czech and Slovak layouts share
+fn cz_sk(code: KeyCode) -> Option<Levels> {
+    use KeyCode::*;
+    Some(match code {
+        Backtick => [ch(';'), ch('°'), NO],
+        Num1 => [ch('+'), ch('1'), ch('~')],
+        Num3 => [ch('š'), ch('3'), dead(Circumflex)],
+        Num4 => [ch('č'), ch('4'), NO],
+        Num6 => [ch('ž'), ch('6'), NO],
+        Num7 => [ch('ý'), ch('7'), dead(Grave)],
+        Num8 => [ch('á'), ch('8'), NO],
+        Num9 => [ch('í'), ch('9'), dead(Acute)],
+        Num0 => [ch('é'), ch('0'), NO],
+        Minus => [ch('='), ch('%'), dead(Diaeresis)],
+        Equals => [dead(Acute), dead(Caron), NO],
+        LeftBracket => [ch('ú'), ch('/'), ch('÷')],
+        Quote => [ch('§'), ch('!'), ch('ß')],
+        Iso102 => [ch('\\'), ch('|'), NO],
+        Q => [ch('q'), ch('Q'), ch('\\')],
+        W => [ch('w'), ch('W'), ch('|')],
+        E => [ch('e'), ch('E'), ch('€')],
+        F => [ch('f'), ch('F'), ch('[')],
+        G => [ch('g'), ch('G'), ch(']')],
+        K => [ch('k'), ch('K'), ch('ł')],
+        L => [ch('l'), ch('L'), ch('Ł')],
+        S => [ch('s'), ch('S'), ch('đ')],
+        D => [ch('d'), ch('D'), ch('Đ')],
+        X => [ch('x'), ch('X'), ch('#')],
+        C => [ch('c'), ch('C'), ch('&')],
+        V => [ch('v'), ch('V'), ch('@')],
+        B => [ch('b'), ch('B'), ch('{')],
+        N => [ch('n'), ch('N'), ch('}')],
+        Comma => [ch(','), ch('?'), ch('<')],
+        Period => [ch('.'), ch(':'), ch('>')],
+        Slash => [ch('-'), ch('_'), ch('*')],
+        // QWERTZ
+        Y => [ch('z'), ch('Z'), NO],
+        Z => [ch('y'), ch('Y'), NO],
+        _ => return common(code),
+    })
+}
+
+fn cz(code: KeyCode) -> Option<Levels> {
+    use KeyCode::*;
+    Some(match code {
+        Num2 => [ch('ě'), ch('2'), dead(Caron)],
+        Num5 => [ch('ř'), ch('5'), dead(Ring)],
+        RightBracket => [ch(')'), ch('('), ch('×')],
+        Backslash => [dead(Diaeresis), ch('\''), ch('¤')],
+        Semicolon => [ch('ů'), ch('"'), ch('$')],
+        _ => return cz_sk(code),
+    })
+}
+
+fn sk(code: KeyCode) -> Option<Levels> {
+    use KeyCode::*;
+    Some(match code {
+        Num2 => [ch('ľ'), ch('2'), dead(Caron)],
+        Num5 => [ch('ť'), ch('5'), dead(Ring)],
+        RightBracket => [ch('ä'), ch('('), ch('×')],
+        Backslash => [ch('ň'), ch(')'), ch('¤')],
+        Semicolon => [ch('ô'), ch('"'), ch('$')],
+        _ => return cz_sk(code),
+    })
+}
+
+/// Symbols without a CP437 glyph, and their closest ASCII character
+fn symbol_fallback(c: char) -> Option<u8> {
+    Some(match c {
+        '×' => b'x',
+        '¤' => b'*',
+        _ => return None,
+    })
+}
+
+fn to_cp437(c: char) -> Option<u8> {
+    cp437::from_char_lossy(c).or_else(|| symbol_fallback(c))
+}
+
+/// A layout and the dead key waiting for its letter
+pub struct Keymap {
+    layout: Layout,
+    dead: Option<DeadKey>,
+}
+
+impl Keymap {
+    pub const fn new(layout: Layout) -> Keymap {
+        Keymap { layout, dead: None }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Switches the layout, forgetting a pending dead key
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+        self.dead = None;
+    }
+
+    fn levels(&self, code: KeyCode) -> Option<Levels> {
+        match self.layout {
+            Layout::Us => us(code),
+            Layout::Cz => cz(code),
+            Layout::Sk => sk(code),
+        }
+    }
+
+    /// The CP437 bytes `event` types. Releases, modifiers, keys held with left
+    /// alt and dead keys type nothing. A letter the accent of a dead key can't
+    /// go on types the accent and then the letter.
+    pub fn translate(&mut self, event: &KeyEvent) -> ArrayVec<u8, 2> {
+        let mut bytes = ArrayVec::new();
+        let modifiers = event.modifiers;
+        if !event.pressed || modifiers.alt() {
+            return bytes;
+        }
+        if modifiers.num_lock() {
+            if let Some(c) = keypad(event.code) {
+                bytes.push(c as u8);
+                return bytes;
+            }
+        }
+        let levels = match self.levels(event.code) {
+            Some(levels) => levels,
+            None => return bytes,
+        };
+
+        let key = if modifiers.alt_gr() {
+            levels[2]
+        } else {
+            let shift = modifiers.shift();
+            match (levels[0], levels[1]) {
+                // caps lock inverts shift for letters, but keeps the digits on
+                // the shift level of the Czech and Slovak number row
+                (Key::Char(normal), shifted) if modifiers.caps_lock() && normal.is_alphabetic() => {
+                    match shifted {
+                        Key::Char(s) if s.is_alphabetic() && shift => Key::Char(normal),
+                        _ if shift => shifted,
+                        _ => normal.to_uppercase().next().map_or(Key::None, Key::Char),
+                    }
+                }
+                (normal, shifted) => {
+                    if shift {
+                        shifted
+                    } else {
+                        normal
+                    }
+                }
+            }
+        };
+
+        match key {
+            Key::None => {}
+            Key::Dead(dead) => {
+                // two dead keys in a row type the first one's accent
+                if let Some(previous) = self.dead.replace(dead) {
+                    self.dead = None;
+                    bytes.push(previous.spacing());
+                }
+            }
+            Key::Char(c) => {
+                if let Some(dead) = self.dead.take() {
+                    if c == ' ' {
+                        bytes.push(dead.spacing());
+                        return bytes;
+                    }
+                    if let Some(composed) = dead.compose(c) {
+                        bytes.extend(to_cp437(composed));
+                        return bytes;
+                    }
+                    // the accent can't go on the letter, both are typed
+                    bytes.push(dead.spacing());
+                }
+                if modifiers.ctrl() && c.is_ascii_alphabetic() {
+                    bytes.push(c as u8 & 0x1F);
+                } else {
+                    bytes.extend(to_cp437(c));
+                }
+            }
+        }
+        bytes
+    }
+}
+
+static KEYMAP: spin::Mutex<Keymap> = spin::Mutex::new(Keymap::new(Layout::Us));
+
+/// Selects the layout used by `translate`
+pub fn set_layout(layout: Layout) {
+    KEYMAP.lock().set_layout(layout);
+    log::info!("Switched the keyboard layout to {:?}", layout);
+}
+
+/// The layout used by `translate`
+pub fn layout() -> Layout {
+    KEYMAP.lock().layout()
+}
+
+/// The CP437 bytes `event` types with the selected layout
+pub fn translate(event: &KeyEvent) -> ArrayVec<u8, 2> {
+    KEYMAP.lock().translate(event)
+}
@@ -0,0 +1,25 @@
+pub mod apic;
+pub mod exceptions;
+pub mod idt;
+pub mod ioapic;
+pub mod irq;
+pub mod pic;
+
+pub use idt::InterruptStackFrame;
+
+/// Loads the IDT with handlers for all CPU exceptions and IRQs.
+/// Interrupts stay disabled until `enable` is called.
+pub fn init() {
+    {
+        let mut idt = idt::idt().lock();
+        exceptions::install(&mut idt);
+        irq::install(&mut idt);
+    }
+    idt::load();
+    log::info!("Loaded the IDT");
+}
+
+/// Starts accepting hardware interrupts
+pub fn enable() {
+    crate::cpu::enable_interrupts();
+}
@@ -0,0 +1,331 @@
+//! The local APIC, and the switch from the 8259 PICs to the I/O APICs.
+//!
+//! `init` takes the APIC layout from the ACPI MADT, masks the 8259s and routes
+//! every ISA IRQ through the I/O APICs to the same vector the PIC used, so
+//! handlers registered with `irq::register` keep working. It also provides
+//! the local APIC timer and inter-processor interrupts.
+
+use super::idt::{Idt, InterruptStackFrame};
+use super::ioapic::IoApic;
+use super::{irq, pic};
+use crate::acpi::{Madt, Polarity, TriggerMode};
+use crate::cpu;
+use crate::memio::{vread32, vwrite32};
+use crate::memory::paging::{self, Flags};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+/// Vector of the local APIC timer
+pub const TIMER_VECTOR: u8 = 0xF0;
+/// Vector of local APIC internal errors
+pub const ERROR_VECTOR: u8 = 0xFE;
+/// Vector of spurious local APIC interrupts, its low 4 bits must be set
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+// Local APIC registers, as offsets from the base
+const REG_ID: u64 = 0x20;
+const REG_VERSION: u64 = 0x30;
+const REG_TPR: u64 = 0x80;
+const REG_EOI: u64 = 0xB0;
+const REG_SVR: u64 = 0xF0;
+const REG_ISR: u64 = 0x100;
+const REG_ESR: u64 = 0x280;
+const REG_ICR_LOW: u64 = 0x300;
+const REG_ICR_HIGH: u64 = 0x310;
+const REG_LVT_TIMER: u64 = 0x320;
+const REG_LVT_LINT0: u64 = 0x350;
+const REG_LVT_LINT1: u64 = 0x360;
+const REG_LVT_ERROR: u64 = 0x370;
+const REG_TIMER_INITIAL: u64 = 0x380;
+const REG_TIMER_CURRENT: u64 = 0x390;
+const REG_TIMER_DIVIDE: u64 = 0x3E0;
+
+const MSR_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const LVT_LEVEL_TRIGGERED: u32 = 1 << 15;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+/// Timer divide configuration value for dividing by 16
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static BASE: AtomicU64 = AtomicU64::new(0);
+static TIMER_TICKS_PER_MS: AtomicU32 = AtomicU32::new(0);
+static TIMER_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+struct Routing {
+    io_apics: Vec<IoApic>,
+    /// global system interrupt of every ISA IRQ line
+    isa_gsi: [Option<u32>; irq::LINES as usize],
+}
+
+static ROUTING: spin::Mutex<Option<Routing>> = spin::Mutex::new(None);
+
+fn read(reg: u64) -> u32 {
+    unsafe { vread32(BASE.load(Ordering::Relaxed) + reg) }
+}
+
+fn write(reg: u64, val: u32) {
+    unsafe { vwrite32(BASE.load(Ordering::Relaxed) + reg, val) }
+}
+
+/// Whether the CPU has a local APIC
+pub fn is_supported() -> bool {
+    cpu::cpuid(1, 0).edx & (1 << 9) != 0
+}
+
+/// Whether interrupts are delivered through the APICs instead of the 8259s
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// The local APIC ID of the running processor
+pub fn id() -> u8 {
+    (read(REG_ID) >> 24) as u8
+}
+
+/// Acknowledges the interrupt currently being handled
+pub fn end_of_interrupt() {
+    write(REG_EOI, 0);
+}
+
+/// Whether `vector` is being handled according to the in-service register
+pub fn in_service(vector: u8) -> bool {
+    read(REG_ISR + 0x10 * (vector as u64 / 32)) & 1 << (vector % 32) != 0
+}
+
+/// Number of local APIC errors seen so far
+pub fn error_count() -> usize {
+    ERRORS.load(Ordering::Relaxed)
+}
+
+fn lvt_flags(polarity: Polarity, trigger: TriggerMode) -> u32 {
+    let mut flags = 0;
+    if polarity == Polarity::ActiveLow {
+        flags |= LVT_ACTIVE_LOW;
+    }
+    if trigger == TriggerMode::Level {
+        flags |= LVT_LEVEL_TRIGGERED;
+    }
+    flags
+}
+
+/// Switches interrupt delivery from the 8259s to the APICs described by `madt`.
+/// Returns `false` and leaves the 8259s active if that isn't possible.
+pub fn init(madt: &Madt) -> bool {
+    if !is_supported() {
+        log::warn!("The CPU has no local APIC, staying with the 8259 PICs");
+        return false;
+    }
+    if madt.io_apics.is_empty() {
+        log::warn!("The MADT lists no I/O APIC, staying with the 8259 PICs");
+        return false;
+    }
+
+    let mut mapper = paging::mapper().lock();
+    if let Err(e) = mapper.identity_map(madt.local_apic_address as usize, 0x1000, Flags::MMIO) {
+        log::warn!("Failed to map the local APIC: {:?}", e);
+        return false;
+    }
+    let mut io_apics = Vec::new();
+    for io_apic in &madt.io_apics {
+        if let Err(e) = mapper.identity_map(io_apic.address as usize, 0x1000, Flags::MMIO) {
+            log::warn!("Failed to map the I/O APIC {}: {:?}", io_apic.id, e);
+            return false;
+        }
+        let mut ioapic = unsafe { IoApic::new(io_apic.address as u64, io_apic.gsi_base) };
+        ioapic.mask_all();
+        io_apics.push(ioapic);
+    }
+    drop(mapper);
+
+    cpu::without_interrupts(|| {
+        BASE.store(madt.local_apic_address, Ordering::Relaxed);
+        unsafe {
+            cpu::wrmsr(MSR_APIC_BASE, cpu::rdmsr(MSR_APIC_BASE) | APIC_BASE_ENABLE);
+        }
+        install(&mut super::idt::idt().lock());
+
+        write(REG_TPR, 0);
+        write(REG_LVT_TIMER, LVT_MASKED);
+        write(REG_LVT_ERROR, ERROR_VECTOR as u32);
+        write(REG_LVT_LINT0, LVT_MASKED);
+        write(REG_LVT_LINT1, LVT_MASKED);
+        let processor_id = madt
+            .local_apics
+            .iter()
+            .find(|l| l.apic_id == id())
+            .map(|l| l.processor_id);
+        for nmi in &madt.nmis {
+            if nmi.processor_id == 0xFF || Some(nmi.processor_id) == processor_id {
+                let reg = if nmi.lint == 0 {
+                    REG_LVT_LINT0
+                } else {
+                    REG_LVT_LINT1
+                };
+                write(reg, LVT_DELIVERY_NMI | lvt_flags(nmi.polarity, nmi.trigger));
+            }
+        }
+        write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+        // clear errors from before the APIC was set up
+        write(REG_ESR, 0);
+
+        let pic_mask = pic::mask();
+        pic::disable();
+        let mut routing = Routing {
+            io_apics,
+            isa_gsi: [None; irq::LINES as usize],
+        };
+        for line in (0..irq::LINES).filter(|&l| l != pic::CASCADE_LINE) {
+            let (gsi, polarity, trigger) = madt.isa_irq(line);
+            let destination = id();
+            if let Some(ioapic) = routing.io_apics.iter_mut().find(|i| i.handles(gsi)) {
+                ioapic.route(gsi, irq::vector(line), destination, polarity, trigger);
+                ioapic.set_masked(gsi, pic_mask & 1 << line != 0);
+                routing.isa_gsi[line as usize] = Some(gsi);
+            }
+        }
+        *ROUTING.lock() = Some(routing);
+        ACTIVE.store(true, Ordering::Release);
+    });
+
+    log::info!(
+        "Switched to the APIC: local APIC {} (version 0x{:x}) at 0x{:x}, {} I/O APIC(s), {} CPU(s)",
+        id(),
+        read(REG_VERSION) & 0xFF,
+        madt.local_apic_address,
+        madt.io_apics.len(),
+        madt.local_apics.iter().filter(|l| l.enabled).count()
+    );
+    for o in &madt.overrides {
+        log::debug!(
+            "ISA IRQ {} is GSI {}, {:?} {:?}",
+            o.source,
+            o.gsi,
+            o.polarity,
+            o.trigger
+        );
+    }
+    true
+}
+
+/// Masks or unmasks the I/O APIC entry of ISA IRQ `line`
+pub fn set_isa_masked(line: u8, masked: bool) {
+    cpu::without_interrupts(|| {
+        if let Some(routing) = ROUTING.lock().as_mut() {
+            if let Some(gsi) = routing.isa_gsi[line as usize] {
+                if let Some(ioapic) = routing.io_apics.iter_mut().find(|i| i.handles(gsi)) {
+                    ioapic.set_masked(gsi, masked);
+                }
+            }
+        }
+    });
+}
+
+/// Measures the local APIC timer frequency against the PIT, in ticks per millisecond
+pub fn calibrate_timer() -> u32 {
+    let ticks = cpu::without_interrupts(|| {
+        write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        write(REG_LVT_TIMER, LVT_MASKED);
+        write(REG_TIMER_INITIAL, u32::MAX);
+        crate::time::pit::wait(10_000);
+        let elapsed = u32::MAX - read(REG_TIMER_CURRENT);
+        write(REG_TIMER_INITIAL, 0);
+        elapsed / 10
+    });
+    TIMER_TICKS_PER_MS.store(ticks, Ordering::Relaxed);
+    log::info!("Local APIC timer runs at {} kHz (divided by 16)", ticks);
+    ticks
+}
+
+/// Calls `handler` `hz` times per second from the local APIC timer.
+/// Calibrates the timer first if that hasn't happened yet.
+pub fn start_timer(hz: u32, handler: fn()) {
+    let mut ticks_per_ms = TIMER_TICKS_PER_MS.load(Ordering::Relaxed);
+    if ticks_per_ms == 0 {
+        ticks_per_ms = calibrate_timer();
+    }
+    TIMER_HANDLER.store(handler as usize, Ordering::Release);
+    let initial = (ticks_per_ms as u64 * 1000 / hz.max(1) as u64).clamp(1, u32::MAX as u64);
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    write(REG_LVT_TIMER, TIMER_VECTOR as u32 | LVT_TIMER_PERIODIC);
+    write(REG_TIMER_INITIAL, initial as u32);
+}
+
+/// Stops the local APIC timer
+pub fn stop_timer() {
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_TIMER_INITIAL, 0);
+}
+
+fn send_icr(destination: u8, command: u32) {
+    cpu::without_interrupts(|| {
+        write(REG_ICR_HIGH, (destination as u32) << 24);
+        write(REG_ICR_LOW, command);
+        while read(REG_ICR_LOW) & ICR_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    });
+}
+
+/// Sends `vector` to the processor with local APIC ID `destination`
+pub fn send_ipi(destination: u8, vector: u8) {
+    send_icr(destination, vector as u32 | ICR_ASSERT);
+}
+
+/// Sends `vector` to all processors except the running one
+pub fn broadcast_ipi(vector: u8) {
+    send_icr(0, vector as u32 | ICR_ASSERT | ICR_ALL_EXCLUDING_SELF);
+}
+
+/// Sends an INIT IPI, resetting the processor `destination`
+pub fn send_init_ipi(destination: u8) {
+    send_icr(destination, ICR_DELIVERY_INIT | ICR_ASSERT);
+}
+
+/// Sends a STARTUP IPI, starting `destination` in real mode at physical address `page * 4096`
+pub fn send_startup_ipi(destination: u8, page: u8) {
+    send_icr(destination, ICR_DELIVERY_STARTUP | ICR_ASSERT | page as u32);
+}
+
+extern "x86-interrupt" fn timer(_frame: InterruptStackFrame) {
+    match TIMER_HANDLER.load(Ordering::Acquire) {
+        0 => {}
+        handler => {
+            // SAFETY: only `fn()`s are ever stored
+            let handler: fn() = unsafe { core::mem::transmute(handler) };
+            handler();
+        }
+    }
+    end_of_interrupt();
+}
+
+extern "x86-interrupt" fn error(_frame: InterruptStackFrame) {
+    // the ESR has to be written before reading it
+    write(REG_ESR, 0);
+    let esr = read(REG_ESR);
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+    log::error!("Local APIC error, ESR: 0x{:x}", esr);
+    end_of_interrupt();
+}
+
+extern "x86-interrupt" fn spurious(_frame: InterruptStackFrame) {
+    // spurious interrupts must not be acknowledged
+}
+
+fn install(idt: &mut Idt) {
+    idt.entry(TIMER_VECTOR).set_handler(timer as usize);
+    idt.entry(ERROR_VECTOR).set_handler(error as usize);
+    idt.entry(SPURIOUS_VECTOR).set_handler(spurious as usize);
+}
@@ -0,0 +1,294 @@
+//! Handlers for the 32 CPU exception vectors.
+//!
+//! Every exception is reported with its vector, error code and the interrupt
+//! stack frame, over the serial log and directly into video memory, the same
+//! way the panic handler does it. Only `#DB`, `NMI` and `#BP` return, all
+//! other exceptions halt the CPU for good.
+//!
+//! Double faults, NMIs and machine checks run on their own IST stacks, so an
+//! overflow of the boot stack into its guard page ends up as a readable
+//! double fault report instead of a triple fault.
+
+use super::idt::{Idt, InterruptStackFrame};
+use crate::tty::display;
+use crate::tty::vgatext::{Color, TextColor};
+
+/// Vector numbers of the CPU exceptions
+pub mod vector {
+    pub const DIVIDE_ERROR: u8 = 0;
+    pub const DEBUG: u8 = 1;
+    pub const NMI: u8 = 2;
+    pub const BREAKPOINT: u8 = 3;
+    pub const OVERFLOW: u8 = 4;
+    pub const BOUND_RANGE: u8 = 5;
+    pub const INVALID_OPCODE: u8 = 6;
+    pub const DEVICE_NOT_AVAILABLE: u8 = 7;
+    pub const DOUBLE_FAULT: u8 = 8;
+    pub const INVALID_TSS: u8 = 10;
+    pub const SEGMENT_NOT_PRESENT: u8 = 11;
+    pub const STACK_SEGMENT: u8 = 12;
+    pub const GENERAL_PROTECTION: u8 = 13;
+    pub const PAGE_FAULT: u8 = 14;
+    pub const X87_FLOATING_POINT: u8 = 16;
+    pub const ALIGNMENT_CHECK: u8 = 17;
+    pub const MACHINE_CHECK: u8 = 18;
+    pub const SIMD_FLOATING_POINT: u8 = 19;
+    pub const VIRTUALIZATION: u8 = 20;
+    pub const CONTROL_PROTECTION: u8 = 21;
+    pub const HYPERVISOR_INJECTION: u8 = 28;
+    pub const VMM_COMMUNICATION: u8 = 29;
+    pub const SECURITY: u8 = 30;
+}
+
+/// Mnemonic and name of every exception vector
+const NAMES: [(&str, &str); 32] = [
+    ("#DE", "Divide Error"),
+    ("#DB", "Debug"),
+    ("NMI", "Non-maskable Interrupt"),
+    ("#BP", "Breakpoint"),
+    ("#OF", "Overflow"),
+    ("#BR", "Bound Range Exceeded"),
+    ("#UD", "Invalid Opcode"),
+    ("#NM", "Device Not Available"),
+    ("#DF", "Double Fault"),
+    ("---", "Coprocessor Segment Overrun"),
+    ("#TS", "Invalid TSS"),
+    ("#NP", "Segment Not Present"),
+    ("#SS", "Stack-Segment Fault"),
+    ("#GP", "General Protection Fault"),
+    ("#PF", "Page Fault"),
+    ("---", "Reserved"),
+    ("#MF", "x87 Floating-Point Exception"),
+    ("#AC", "Alignment Check"),
+    ("#MC", "Machine Check"),
+    ("#XM", "SIMD Floating-Point Exception"),
+    ("#VE", "Virtualization Exception"),
+    ("#CP", "Control Protection Exception"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("#HV", "Hypervisor Injection Exception"),
+    ("#VC", "VMM Communication Exception"),
+    ("#SX", "Security Exception"),
+    ("---", "Reserved"),
+];
+
+/// Mnemonic and name of an exception vector
+pub fn name(vector: u8) -> (&'static str, &'static str) {
+    NAMES
+        .get(vector as usize)
+        .copied()
+        .unwrap_or(("---", "Unknown"))
+}
+
+/// Describes the bits of a page fault error code
+struct PageFaultCause(u64);
+
+impl core::fmt::Display for PageFaultCause {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let code = self.0;
+        f.write_str(if code & 1 != 0 {
+            "protection violation"
+        } else {
+            "page not present"
+        })?;
+        f.write_str(if code & 1 << 1 != 0 {
+            ", write"
+        } else {
+            ", read"
+        })?;
+        f.write_str(if code & 1 << 2 != 0 {
+            ", user"
+        } else {
+            ", kernel"
+        })?;
+        if code & 1 << 3 != 0 {
+            f.write_str(", reserved bit set")?;
+        }
+        if code & 1 << 4 != 0 {
+            f.write_str(", instruction fetch")?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the exception report to the serial log and the screen
+fn report(vector: u8, error_code: Option<u64>, frame: &InterruptStackFrame) {
+    let (mnemonic, name) = name(vector);
+    let cr2 = crate::cpu::read_cr2();
+
+    log::error!("EXCEPTION {} {} (vector {})", mnemonic, name, vector);
+    if let Some(code) = error_code {
+        log::error!("  error code: 0x{:x}", code);
+    }
+    log::error!(
+        "  RIP: 0x{:016x} CS: 0x{:04x} RFLAGS: 0x{:016x}",
+        frame.rip,
+        frame.cs,
+        frame.rflags
+    );
+    log::error!("  RSP: 0x{:016x} SS: 0x{:04x}", frame.rsp, frame.ss);
+    if vector == vector::PAGE_FAULT {
+        log::error!(
+            "  CR2: 0x{:016x} ({})",
+            cr2,
+            PageFaultCause(error_code.unwrap_or(0))
+        );
+    }
+
+    let guard = crate::memory::boot_stack_guard();
+    let stack_overflow = vector == vector::DOUBLE_FAULT
+        && (guard..guard + crate::memory::PAGE_SIZE).contains(&(cr2 as usize));
+    if stack_overflow {
+        log::error!(
+            "  kernel stack overflow, CR2 0x{:x} is in the boot stack guard page",
+            cr2
+        );
+    }
+
+    let color = TextColor::new(Color::White, Color::Red);
+    let mut pos = display::write_fmt_direct(
+        0,
+        color,
+        format_args!("EXCEPTION {} {} (vector {})\n", mnemonic, name, vector),
+    );
+    if let Some(code) = error_code {
+        pos = display::write_fmt_direct(pos, color, format_args!("error code: 0x{:x}\n", code));
+    }
+    pos = display::write_fmt_direct(
+        pos,
+        color,
+        format_args!(
+            "RIP: 0x{:016x} CS: 0x{:04x} RFLAGS: 0x{:016x}\nRSP: 0x{:016x} SS: 0x{:04x}\n",
+            frame.rip, frame.cs, frame.rflags, frame.rsp, frame.ss
+        ),
+    );
+    if stack_overflow {
+        pos = display::write_fmt_direct(
+            pos,
+            color,
+            format_args!(
+                "kernel stack overflow, CR2 0x{:x} is in the boot stack guard page\n",
+                cr2
+            ),
+        );
+    }
+    if vector == vector::PAGE_FAULT {
+        display::write_fmt_direct(
+            pos,
+            color,
+            format_args!(
+                "CR2: 0x{:016x} ({})\n",
+                cr2,
+                PageFaultCause(error_code.unwrap_or(0))
+            ),
+        );
+    }
+}
+
+/// Defines a handler for an exception vector.
+/// `resume` handlers return to the interrupted code, all others halt.
+macro_rules! exception_handler {
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame) -> ! {
+            report($vector, None, &frame);
+            crate::cpu::halt_forever()
+        }
+    };
+    ($name:ident, $vector:expr, error_code) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame, error_code: u64) -> ! {
+            report($vector, Some(error_code), &frame);
+            crate::cpu::halt_forever()
+        }
+    };
+    ($name:ident, $vector:expr, resume) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame) {
+            report($vector, None, &frame);
+        }
+    };
+}
+
+exception_handler!(divide_error, 0);
+exception_handler!(debug, 1, resume);
+exception_handler!(nmi, 2, resume);
+exception_handler!(breakpoint, 3, resume);
+exception_handler!(overflow, 4);
+exception_handler!(bound_range, 5);
+exception_handler!(invalid_opcode, 6);
+exception_handler!(device_not_available, 7);
+exception_handler!(double_fault, 8, error_code);
+exception_handler!(coprocessor_segment_overrun, 9);
+exception_handler!(invalid_tss, 10, error_code);
+exception_handler!(segment_not_present, 11, error_code);
+exception_handler!(stack_segment, 12, error_code);
+exception_handler!(general_protection, 13, error_code);
+exception_handler!(page_fault, 14, error_code);
+exception_handler!(reserved_15, 15);
+exception_handler!(x87_floating_point, 16);
+exception_handler!(alignment_check, 17, error_code);
+exception_handler!(machine_check, 18);
+exception_handler!(simd_floating_point, 19);
+exception_handler!(virtualization, 20);
+exception_handler!(control_protection, 21, error_code);
+exception_handler!(reserved_22, 22);
+exception_handler!(reserved_23, 23);
+exception_handler!(reserved_24, 24);
+exception_handler!(reserved_25, 25);
+exception_handler!(reserved_26, 26);
+exception_handler!(reserved_27, 27);
+exception_handler!(hypervisor_injection, 28);
+exception_handler!(vmm_communication, 29, error_code);
+exception_handler!(security, 30, error_code);
+exception_handler!(reserved_31, 31);
+
+/// Installs the handlers for vectors 0-31
+pub fn install(idt: &mut Idt) {
+    let handlers: [usize; 32] = [
+        divide_error as usize,
+        debug as usize,
+        nmi as usize,
+        breakpoint as usize,
+        overflow as usize,
+        bound_range as usize,
+        invalid_opcode as usize,
+        device_not_available as usize,
+        double_fault as usize,
+        coprocessor_segment_overrun as usize,
+        invalid_tss as usize,
+        segment_not_present as usize,
+        stack_segment as usize,
+        general_protection as usize,
+        page_fault as usize,
+        reserved_15 as usize,
+        x87_floating_point as usize,
+        alignment_check as usize,
+        machine_check as usize,
+        simd_floating_point as usize,
+        virtualization as usize,
+        control_protection as usize,
+        reserved_22 as usize,
+        reserved_23 as usize,
+        reserved_24 as usize,
+        reserved_25 as usize,
+        reserved_26 as usize,
+        reserved_27 as usize,
+        hypervisor_injection as usize,
+        vmm_communication as usize,
+        security as usize,
+        reserved_31 as usize,
+    ];
+    for (vector, &handler) in handlers.iter().enumerate() {
+        idt.entry(vector as u8).set_handler(handler);
+    }
+    // allow `int3` from user mode
+    idt.entry(vector::BREAKPOINT).set_privilege_level(3);
+    // these can't trust the current stack
+    idt.entry(vector::DOUBLE_FAULT)
+        .set_ist(crate::gdt::DOUBLE_FAULT_IST);
+    idt.entry(vector::NMI).set_ist(crate::gdt::NMI_IST);
+    idt.entry(vector::MACHINE_CHECK)
+        .set_ist(crate::gdt::MACHINE_CHECK_IST);
+}
@@ -0,0 +1,112 @@
+/// The values the CPU pushes onto the stack before calling an interrupt handler
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptStackFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// A 64-bit interrupt gate descriptor
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Entry {
+    offset_low: u16,
+    selector: u16,
+    /// IST index in bits 0-2, gate type in 8-11, DPL in 13-14, present in 15
+    options: u16,
+    offset_mid: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+static_assertions::assert_eq_size!(Entry, [u8; 16]);
+
+impl Entry {
+    const fn missing() -> Entry {
+        Entry {
+            offset_low: 0,
+            selector: 0,
+            options: 0,
+            offset_mid: 0,
+            offset_high: 0,
+            reserved: 0,
+        }
+    }
+
+    /// Points the gate at `handler`, an `extern "x86-interrupt" fn`.
+    /// Interrupts are disabled while the handler runs.
+    pub fn set_handler(&mut self, handler: usize) -> &mut Self {
+        self.offset_low = handler as u16;
+        self.offset_mid = (handler >> 16) as u16;
+        self.offset_high = (handler >> 32) as u32;
+        self.selector = crate::cpu::read_cs();
+        // present, interrupt gate, DPL 0, no IST
+        self.options = (1 << 15) | (0xE << 8);
+        self
+    }
+
+    /// Runs the handler on stack `index` (1-7) of the interrupt stack table, 0 disables it
+    pub fn set_ist(&mut self, index: u8) -> &mut Self {
+        if index > 7 {
+            panic!("Entry::set_ist({}): invalid IST index", index);
+        }
+        self.options = (self.options & !0b111) | index as u16;
+        self
+    }
+
+    /// Allows the interrupt to be raised with `int` from the given privilege level
+    pub fn set_privilege_level(&mut self, dpl: u8) -> &mut Self {
+        self.options = (self.options & !(0b11 << 13)) | ((dpl as u16 & 0b11) << 13);
+        self
+    }
+
+    /// Whether a handler is installed
+    pub fn is_present(&self) -> bool {
+        self.options & (1 << 15) != 0
+    }
+}
+
+/// The interrupt descriptor table
+#[repr(C, align(16))]
+pub struct Idt {
+    entries: [Entry; 256],
+}
+
+impl Idt {
+    const fn new() -> Idt {
+        Idt {
+            entries: [Entry::missing(); 256],
+        }
+    }
+
+    /// The gate for `vector`
+    pub fn entry(&mut self, vector: u8) -> &mut Entry {
+        &mut self.entries[vector as usize]
+    }
+}
+
+#[repr(C, packed)]
+struct DescriptorPointer {
+    limit: u16,
+    base: u64,
+}
+
+static IDT: spin::Mutex<Idt> = spin::Mutex::new(Idt::new());
+
+/// The IDT, changes take effect immediately once it's loaded
+pub fn idt() -> &'static spin::Mutex<Idt> {
+    &IDT
+}
+
+/// Loads the IDT into the CPU
+pub fn load() {
+    let idt = IDT.lock();
+    let pointer = DescriptorPointer {
+        limit: (core::mem::size_of::<Idt>() - 1) as u16,
+        base: &*idt as *const Idt as u64,
+    };
+    unsafe { asm!("lidt [{}]", in(reg) &pointer, options(readonly, nostack)) };
+}
@@ -0,0 +1,112 @@
+//! I/O APIC driver, routing global system interrupts to local APIC vectors
+
+use crate::acpi::{Polarity, TriggerMode};
+use crate::memio::{vread32, vwrite32};
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+const REG_ID: u32 = 0x00;
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION: u32 = 0x10;
+
+const MASKED: u64 = 1 << 16;
+const LEVEL_TRIGGERED: u64 = 1 << 15;
+const ACTIVE_LOW: u64 = 1 << 13;
+
+/// A single I/O APIC, whose registers have been mapped
+pub struct IoApic {
+    base: u64,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    /// # Safety
+    /// `base` must be the mapped register window of an I/O APIC
+    pub unsafe fn new(base: u64, gsi_base: u32) -> IoApic {
+        let mut ioapic = IoApic {
+            base,
+            gsi_base,
+            entries: 0,
+        };
+        ioapic.entries = (ioapic.read(REG_VERSION) >> 16 & 0xFF) + 1;
+        ioapic
+    }
+
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            vwrite32(self.base + IOREGSEL, reg);
+            vread32(self.base + IOWIN)
+        }
+    }
+
+    fn write(&mut self, reg: u32, val: u32) {
+        unsafe {
+            vwrite32(self.base + IOREGSEL, reg);
+            vwrite32(self.base + IOWIN, val);
+        }
+    }
+
+    /// The APIC ID of the I/O APIC
+    pub fn id(&self) -> u8 {
+        (self.read(REG_ID) >> 24) as u8
+    }
+
+    /// Whether `gsi` is handled by this I/O APIC
+    pub fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    /// The range of global system interrupts handled by this I/O APIC
+    pub fn gsi_range(&self) -> core::ops::Range<u32> {
+        self.gsi_base..self.gsi_base + self.entries
+    }
+
+    fn redirection(&self, gsi: u32) -> u64 {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        (self.read(reg + 1) as u64) << 32 | self.read(reg) as u64
+    }
+
+    fn set_redirection(&mut self, gsi: u32, entry: u64) {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        // mask first, so that a half written entry never fires
+        self.write(reg, MASKED as u32);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+
+    /// Routes `gsi` to `vector` on the local APIC `destination`, in fixed delivery mode.
+    /// The entry starts out masked.
+    pub fn route(
+        &mut self,
+        gsi: u32,
+        vector: u8,
+        destination: u8,
+        polarity: Polarity,
+        trigger: TriggerMode,
+    ) {
+        let mut entry = vector as u64 | MASKED | (destination as u64) << 56;
+        if polarity == Polarity::ActiveLow {
+            entry |= ACTIVE_LOW;
+        }
+        if trigger == TriggerMode::Level {
+            entry |= LEVEL_TRIGGERED;
+        }
+        self.set_redirection(gsi, entry);
+    }
+
+    /// Masks or unmasks `gsi`
+    pub fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let entry = self.redirection(gsi);
+        self.set_redirection(gsi, if masked { entry | MASKED } else { entry & !MASKED });
+    }
+
+    /// Masks every entry
+    pub fn mask_all(&mut self) {
+        for gsi in self.gsi_range() {
+            let entry = self.redirection(gsi);
+            self.set_redirection(gsi, entry | MASKED);
+        }
+    }
+}
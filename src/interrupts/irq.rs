@@ -0,0 +1,178 @@
+//! Hardware interrupt dispatch.
+//!
+//! Each of the 16 ISA IRQ lines gets an IDT entry which calls the handler
+//! registered with `register` and acknowledges the interrupt afterwards.
+//! The lines arrive through the 8259 PICs until `apic::init` switches to the
+//! I/O APICs, which keep delivering them on the same vectors.
+//! Handlers run with interrupts disabled and must not take locks which are
+//! held with interrupts enabled elsewhere, see `cpu::without_interrupts`.
+
+use super::idt::{Idt, InterruptStackFrame};
+use super::{apic, pic};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of ISA IRQ lines
+pub const LINES: u8 = 16;
+
+/// IRQ line of the PIT
+pub const TIMER: u8 = 0;
+/// IRQ line of the PS/2 keyboard
+pub const KEYBOARD: u8 = 1;
+/// IRQ line of COM2 and COM4
+pub const COM2: u8 = 3;
+/// IRQ line of COM1 and COM3
+pub const COM1: u8 = 4;
+/// IRQ line of the PS/2 mouse
+pub const MOUSE: u8 = 12;
+
+/// An IRQ handler
+pub type Handler = fn();
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static HANDLERS: [AtomicUsize; LINES as usize] = [NO_HANDLER; LINES as usize];
+static SPURIOUS: AtomicUsize = AtomicUsize::new(0);
+static UNHANDLED: AtomicUsize = AtomicUsize::new(0);
+
+/// The IDT vector IRQ `line` is delivered on
+pub fn vector(line: u8) -> u8 {
+    pic::MASTER_OFFSET + line
+}
+
+fn check_line(line: u8, fname: &str) {
+    if line >= LINES {
+        panic!("irq::{}({}): invalid IRQ line", fname, line);
+    }
+}
+
+/// Registers `handler` for IRQ `line` and unmasks the line.
+/// Panics if the line already has a handler.
+pub fn register(line: u8, handler: Handler) {
+    check_line(line, "register");
+    if HANDLERS[line as usize]
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        panic!("irq::register({}, ..): line already has a handler", line);
+    }
+    unmask(line);
+    log::debug!("Registered a handler for IRQ {}", line);
+}
+
+/// Masks IRQ `line` and removes its handler
+pub fn unregister(line: u8) {
+    check_line(line, "unregister");
+    mask(line);
+    HANDLERS[line as usize].store(0, Ordering::Release);
+}
+
+/// Stops IRQ `line` from being delivered
+pub fn mask(line: u8) {
+    check_line(line, "mask");
+    if apic::is_active() {
+        apic::set_isa_masked(line, true);
+    } else {
+        pic::mask_line(line);
+    }
+}
+
+/// Lets IRQ `line` be delivered
+pub fn unmask(line: u8) {
+    check_line(line, "unmask");
+    if apic::is_active() {
+        apic::set_isa_masked(line, false);
+    } else {
+        pic::unmask_line(line);
+    }
+}
+
+/// Number of spurious IRQs seen so far
+pub fn spurious_count() -> usize {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+/// Number of IRQs which arrived without a registered handler
+pub fn unhandled_count() -> usize {
+    UNHANDLED.load(Ordering::Relaxed)
+}
+
+fn dispatch(line: u8) {
+    if apic::is_active() {
+        // an interrupt the 8259s raised before being masked isn't in service
+        // on the local APIC and must not be acknowledged there
+        if !apic::in_service(vector(line)) {
+            SPURIOUS.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    } else if pic::is_spurious(line) {
+        SPURIOUS.fetch_add(1, Ordering::Relaxed);
+        pic::end_of_spurious_interrupt(line);
+        return;
+    }
+    match HANDLERS[line as usize].load(Ordering::Acquire) {
+        0 => {
+            UNHANDLED.fetch_add(1, Ordering::Relaxed);
+        }
+        handler => {
+            // SAFETY: only `Handler`s are ever stored
+            let handler: Handler = unsafe { core::mem::transmute(handler) };
+            handler();
+        }
+    }
+    if apic::is_active() {
+        apic::end_of_interrupt();
+    } else {
+        pic::end_of_interrupt(line);
+    }
+}
+
+macro_rules! irq_stub {
+    ($name:ident, $line:expr) => {
+        extern "x86-interrupt" fn $name(_frame: InterruptStackFrame) {
+            dispatch($line);
+        }
+    };
+}
+
+irq_stub!(irq0, 0);
+irq_stub!(irq1, 1);
+irq_stub!(irq2, 2);
+irq_stub!(irq3, 3);
+irq_stub!(irq4, 4);
+irq_stub!(irq5, 5);
+irq_stub!(irq6, 6);
+irq_stub!(irq7, 7);
+irq_stub!(irq8, 8);
+irq_stub!(irq9, 9);
+irq_stub!(irq10, 10);
+irq_stub!(irq11, 11);
+irq_stub!(irq12, 12);
+irq_stub!(irq13, 13);
+irq_stub!(irq14, 14);
+irq_stub!(irq15, 15);
+
+/// Installs the IRQ entries into the IDT and remaps the PICs
+pub fn install(idt: &mut Idt) {
+    let stubs: [usize; LINES as usize] = [
+        irq0 as usize,
+        irq1 as usize,
+        irq2 as usize,
+        irq3 as usize,
+        irq4 as usize,
+        irq5 as usize,
+        irq6 as usize,
+        irq7 as usize,
+        irq8 as usize,
+        irq9 as usize,
+        irq10 as usize,
+        irq11 as usize,
+        irq12 as usize,
+        irq13 as usize,
+        irq14 as usize,
+        irq15 as usize,
+    ];
+    for (line, &stub) in stubs.iter().enumerate() {
+        idt.entry(vector(line as u8)).set_handler(stub);
+    }
+    pic::init();
+}
@@ -0,0 +1,119 @@
+//! The two cascaded legacy 8259 programmable interrupt controllers.
+//!
+//! At boot they deliver IRQs 0-7 on vectors 8-15, which collide with the CPU
+//! exceptions, so `init` remaps them to `MASTER_OFFSET` and `SLAVE_OFFSET`.
+
+use crate::memio::{inb, io_wait, outb};
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xA0;
+const SLAVE_DATA: u16 = 0xA1;
+
+/// ICW1: initialization, ICW4 follows
+const ICW1_INIT: u8 = 0x11;
+/// ICW4: 8086 mode
+const ICW4_8086: u8 = 0x01;
+/// OCW2: non-specific end of interrupt
+const EOI: u8 = 0x20;
+/// OCW3: the next command port read returns the in-service register
+const READ_ISR: u8 = 0x0B;
+
+/// The IRQ line the slave PIC is connected to on the master
+pub const CASCADE_LINE: u8 = 2;
+
+/// Vector of IRQ 0
+pub const MASTER_OFFSET: u8 = 32;
+/// Vector of IRQ 8
+pub const SLAVE_OFFSET: u8 = MASTER_OFFSET + 8;
+
+/// Remaps both PICs and masks every line except the cascade
+pub fn init() {
+    unsafe {
+        outb(MASTER_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(SLAVE_COMMAND, ICW1_INIT);
+        io_wait();
+        // ICW2: vector offsets
+        outb(MASTER_DATA, MASTER_OFFSET);
+        io_wait();
+        outb(SLAVE_DATA, SLAVE_OFFSET);
+        io_wait();
+        // ICW3: the slave sits on IRQ 2 of the master, and has cascade identity 2
+        outb(MASTER_DATA, 1 << CASCADE_LINE);
+        io_wait();
+        outb(SLAVE_DATA, CASCADE_LINE);
+        io_wait();
+        outb(MASTER_DATA, ICW4_8086);
+        io_wait();
+        outb(SLAVE_DATA, ICW4_8086);
+        io_wait();
+    }
+    set_mask(!(1 << CASCADE_LINE));
+    log::info!(
+        "Remapped the 8259 PICs to vectors {}-{}",
+        MASTER_OFFSET,
+        SLAVE_OFFSET + 7
+    );
+}
+
+/// The interrupt masks of both PICs, bit `n` masks IRQ `n`
+pub fn mask() -> u16 {
+    unsafe { (inb(SLAVE_DATA) as u16) << 8 | inb(MASTER_DATA) as u16 }
+}
+
+/// Overwrites the interrupt masks of both PICs
+pub fn set_mask(mask: u16) {
+    unsafe {
+        outb(MASTER_DATA, mask as u8);
+        outb(SLAVE_DATA, (mask >> 8) as u8);
+    }
+}
+
+/// Stops the PIC from delivering IRQ `line`
+pub fn mask_line(line: u8) {
+    set_mask(mask() | 1 << line);
+}
+
+/// Lets the PIC deliver IRQ `line`
+pub fn unmask_line(line: u8) {
+    set_mask(mask() & !(1 << line));
+}
+
+/// Masks all lines, for when the APIC takes over
+pub fn disable() {
+    set_mask(0xFFFF);
+}
+
+/// The in-service registers of both PICs, bit `n` is set while IRQ `n` is being handled
+pub fn in_service() -> u16 {
+    unsafe {
+        outb(MASTER_COMMAND, READ_ISR);
+        outb(SLAVE_COMMAND, READ_ISR);
+        (inb(SLAVE_COMMAND) as u16) << 8 | inb(MASTER_COMMAND) as u16
+    }
+}
+
+/// Whether IRQ `line` is spurious, i.e. it was raised but isn't in service.
+/// Only IRQ 7 and 15 can be spurious.
+pub fn is_spurious(line: u8) -> bool {
+    (line == 7 || line == 15) && in_service() & 1 << line == 0
+}
+
+/// Acknowledges IRQ `line`, the slave also needs an EOI for lines 8-15
+pub fn end_of_interrupt(line: u8) {
+    unsafe {
+        if line >= 8 {
+            outb(SLAVE_COMMAND, EOI);
+        }
+        outb(MASTER_COMMAND, EOI);
+    }
+}
+
+/// Acknowledges a spurious IRQ. A spurious IRQ 15 was still
+/// forwarded by the master, which needs an EOI for the cascade.
+pub fn end_of_spurious_interrupt(line: u8) {
+    if line == 15 {
+        unsafe { outb(MASTER_COMMAND, EOI) }
+    }
+}
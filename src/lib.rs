@@ -1,50 +1,171 @@
 #![no_std]
 #![feature(asm)]
 #![feature(prelude_import)]
+#![feature(alloc_error_handler)]
+#![feature(abi_x86_interrupt)]
+#![feature(const_fn_trait_bound)]
 
+extern crate alloc;
+
+pub mod acpi;
+pub mod cpu;
+pub mod gdt;
+pub mod input;
+pub mod interrupts;
+pub mod logging;
 pub mod memio;
+pub mod memory;
+pub mod multiboot;
+pub mod ps2;
+pub mod serial;
+pub mod time;
 pub mod tty;
 pub mod util;
-pub mod logging;
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    let mut i = 0;
-    let _ = util::text::format_apply(
-        |s| {
-            for ch in s.as_bytes() {
-                if i == tty::vgatext::HEIGHT * tty::vgatext::WIDTH - 1 {
-                    #[allow(clippy::empty_loop)]
-                    loop {}
-                }
-                unsafe {
-                    tty::vgatext::writechar(
-                        (i % tty::vgatext::WIDTH, i / tty::vgatext::WIDTH),
-                        tty::vgatext::Character::from_ascii(*ch),
-                    )
-                };
-                i += 1;
-            }
-            Ok(())
-        },
-        format_args!("{}", info),
-    );
+    use core::fmt::Write;
+    // SAFETY: the panicking code, which may hold the framebuffer console, never continues
+    unsafe { tty::framebuffer::force_unlock() };
+    let color = tty::TextColor::default();
+    let end = tty::display::write_fmt_direct(0, color, format_args!("{}\n", info));
+    // the latest records below the message, all of them to the serial port
+    let (width, height) = tty::display::dimensions();
+    // the framebuffer console has no size until its first font is set
+    if width != 0 && height != 0 {
+        let mut pos = end;
+        let last = logging::dmesg::next_seq();
+        let rows = height.saturating_sub(end / width) as u64;
+        logging::dmesg::for_each(last.saturating_sub(rows), last, |record| {
+            pos = tty::display::write_fmt_direct(
+                pos,
+                color,
+                format_args!(
+                    "[{}] [{}] {}\n",
+                    time::Uptime(record.timestamp),
+                    record.level,
+                    record.message
+                ),
+            );
+        });
+    }
+    let mut com1 = serial::Writer(serial::Com::Com1);
+    let _ = writeln!(com1, "Kernel panic: {}\n--- kernel log ---", info);
+    let _ = logging::dump(&mut com1);
 
     #[allow(clippy::empty_loop)]
     loop {}
 }
 
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    let stats = memory::heap::stats();
+    log::error!(
+        "Failed to allocate {} bytes aligned to {}, heap: {} of {} bytes used",
+        layout.size(),
+        layout.align(),
+        stats.used,
+        stats.size
+    );
+    panic!("alloc_error({:?}): out of kernel heap memory", layout);
+}
+
 #[no_mangle]
-pub extern "C" fn kmain() -> ! {
+pub extern "C" fn kmain(multiboot_magic: u32, multiboot_info: usize) -> ! {
     logging::init().unwrap();
+    time::init();
     log::info!("Started up kernel and initialized logging");
-    tty::init();
+    gdt::init();
+    interrupts::init();
+    serial::init();
+    let boot_info = multiboot::init(multiboot_magic, multiboot_info);
+    let option = |name: &str| {
+        boot_info.command_line().and_then(|cmdline| {
+            cmdline
+                .split_whitespace()
+                .find_map(|arg| arg.strip_prefix(name))
+        })
+    };
+    if let Some(spec) = option("log=") {
+        if let Err(e) = logging::set_filter_spec(spec) {
+            log::warn!("Invalid log filter '{}': {:?}", spec, e);
+        }
+    }
+    memory::init(boot_info);
+    if acpi::init(boot_info) {
+        if let Some(madt) = acpi::madt() {
+            interrupts::apic::init(&madt);
+        }
+    }
+    tty::init(boot_info);
+    logging::add_sink(&logging::sink::Console);
+    if serial::is_present(serial::Com::Com1) {
+        tty::attach_serial(serial::Com::Com1);
+    }
+    if let Some(mode) = option("vga=") {
+        match tty::Mode::from_name(mode) {
+            Some(mode) => tty::set_mode(mode),
+            None => log::warn!(
+                "Unknown text mode '{}', use 80x25, 80x50, 90x60 or 40x25",
+                mode
+            ),
+        }
+    }
+    if let Some(theme) = option("theme=") {
+        match tty::Palette::from_name(theme) {
+            Some(palette) => {
+                for index in 0..tty::CONSOLES {
+                    tty::console(index).lock().set_palette(&palette);
+                }
+            }
+            None => log::warn!(
+                "Unknown theme '{}', use vga, solarized, high-contrast or amber",
+                theme
+            ),
+        }
+    }
+    if let Some(name) = option("kbd=") {
+        match input::keymap::Layout::from_name(name) {
+            Some(layout) => input::keymap::set_layout(layout),
+            None => log::warn!("Unknown keyboard layout '{}', use us, cz or sk", name),
+        }
+    }
+    match ps2::init() {
+        Ok(ports) if ports.first => {
+            if let Err(e) = ps2::keyboard::init() {
+                log::warn!("Failed to initialize the PS/2 keyboard: {:?}", e);
+            }
+        }
+        Ok(_) => log::warn!("The PS/2 controller has no working keyboard port"),
+        Err(e) => log::warn!("Failed to initialize the PS/2 controller: {:?}", e),
+    }
+
+    interrupts::enable();
+
+    #[cfg(feature = "tty-bench")]
+    tty::benchmark(10_000);
 
     kprintln!("Hello World!");
 
     loop {
-        unsafe {
-            asm!("nop")
+        let (console, bytes) = match input::wait() {
+            input::Event::Key(event) => {
+                if tty::handle_key(&event) {
+                    continue;
+                }
+                (tty::active(), input::keymap::translate(&event))
+            }
+            // the serial terminal shows the kernel's console, not the one on screen
+            input::Event::Char(byte) => (tty::tty(), core::iter::once(byte).collect()),
+        };
+        if !bytes.is_empty() {
+            let mut tty = console.lock();
+            let color = tty.color();
+            tty.scroll_to_bottom();
+            for byte in bytes {
+                tty.append_char(tty::Character::new(byte, color));
+            }
+            tty.flush();
         }
     }
 }
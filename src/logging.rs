@@ -1,35 +1,139 @@
-/// Logs to serial port, mostly for QEMU
-struct SerialLogger;
+//! The kernel log.
+//!
+//! Records are kept in the `dmesg` buffer and written to the sinks added with
+//! `add_sink`, which catch up on the records logged before them. Records are
+//! filtered by level and module with a `Filter`, which `kmain` takes from the
+//! `log=` option of the kernel command line and which can be changed at any
+//! time with `set_filter`. With the `release-no-trace` feature trace records
+//! are compiled out of release builds.
 
-impl log::Log for SerialLogger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
+pub mod dmesg;
+mod filter;
+pub mod sink;
+
+pub use filter::{Error, Filter};
+pub use sink::Sink;
+
+use crate::cpu;
+use crate::serial::Com;
+use arrayvec::{ArrayString, ArrayVec};
+use log::LevelFilter;
+
+/// Number of sinks records can be written to
+pub const MAX_SINKS: usize = 4;
+
+/// Only accessed with interrupts disabled, as the logger may run in an interrupt handler
+static FILTER: spin::Mutex<Filter> = spin::Mutex::new(Filter::new(LevelFilter::Debug));
+/// Only accessed with interrupts disabled, like `FILTER`
+static SINKS: spin::Mutex<ArrayVec<&'static dyn Sink, MAX_SINKS>> =
+    spin::Mutex::new(ArrayVec::new_const());
+
+/// Keeps records in `dmesg` and writes them to the sinks
+struct KernelLogger;
+
+impl log::Log for KernelLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        cpu::without_interrupts(|| FILTER.lock().enabled(metadata))
     }
 
     fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) {
-            let _ = crate::util::text::format_apply(
-                |s| {
-                    for &b in s.as_bytes() {
-                        unsafe { crate::memio::outb(0x3F8, b) }
-                    } 
-                    Ok(())
-                },
-                format_args!(
-                    "[{}]@{}:{}> {}\n",
-                    record.level(),
-                    record.file().unwrap_or("none"),
-                    record.line().unwrap_or(0),
-                    record.args()
-                ),
-            );
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut message = ArrayString::<{ dmesg::MESSAGE_LEN }>::new();
+        let _ = crate::util::text::format_apply(
+            |s| {
+                message.push_str(crate::util::text::truncate(s, message.remaining_capacity()));
+                Ok(())
+            },
+            *record.args(),
+        );
+        let timestamp = crate::time::uptime_us();
+        let (level, file, line) = (record.level(), record.file_static(), record.line());
+        let line = line.unwrap_or(0);
+        let record = dmesg::Record {
+            seq: dmesg::push(timestamp, level, file, line, &message),
+            timestamp,
+            level,
+            file,
+            line,
+            message: &message,
+        };
+        // a sink being added is caught up through the buffer
+        let sinks = cpu::without_interrupts(|| SINKS.try_lock().map(|sinks| sinks.clone()));
+        for sink in sinks.iter().flatten() {
+            sink.write(&record);
         }
     }
 
     fn flush(&self) {}
 }
-static LOGGER: SerialLogger = SerialLogger;
+static LOGGER: KernelLogger = KernelLogger;
 
+/// Sets up the logger, writing to COM1 and QEMU's debug console if there is one
 pub fn init() -> Result<(), log::SetLoggerError> {
-    log::set_logger(&LOGGER).map(|_| log::set_max_level(log::LevelFilter::Debug))
+    log::set_logger(&LOGGER).map(|_| log::set_max_level(filter().max_level()))?;
+    add_sink(&sink::Serial(Com::Com1));
+    if sink::DebugCon::is_present() {
+        add_sink(&sink::DebugCon);
+    }
+    Ok(())
+}
+
+/// Writes the records kept in `dmesg` and all further ones to `sink`.
+/// Panics if there are `MAX_SINKS` sinks already.
+pub fn add_sink(sink: &'static dyn Sink) {
+    let end = cpu::without_interrupts(|| {
+        if SINKS.lock().try_push(sink).is_err() {
+            panic!(
+                "logging::add_sink(..): there are {} sinks already",
+                MAX_SINKS
+            );
+        }
+        dmesg::next_seq()
+    });
+    dmesg::for_each(0, end, |record| sink.write(record));
+}
+
+/// Writes the records kept in `dmesg` to `w`, one per line
+pub fn dump<W: core::fmt::Write>(w: &mut W) -> core::fmt::Result {
+    let mut result = Ok(());
+    dmesg::for_each(0, dmesg::next_seq(), |record| {
+        result = result.and_then(|_| writeln!(w, "{}", record));
+    });
+    result
+}
+
+/// The filter records are logged through
+pub fn filter() -> Filter {
+    cpu::without_interrupts(|| FILTER.lock().clone())
+}
+
+/// Logs the records `filter` lets through from now on
+pub fn set_filter(filter: Filter) {
+    cpu::without_interrupts(|| {
+        // the log macros skip anything above the max level without asking the logger
+        log::set_max_level(filter.max_level());
+        *FILTER.lock() = filter;
+    });
+}
+
+/// Parses `spec` like `info,os::tty=trace` and uses it as the filter, see `Filter::parse`
+pub fn set_filter_spec(spec: &str) -> Result<(), Error<'_>> {
+    Filter::parse(spec).map(set_filter)
+}
+
+/// Sets the level of modules the filter has no level for
+pub fn set_level(level: LevelFilter) {
+    let mut filter = filter();
+    filter.set_default_level(level);
+    set_filter(filter);
+}
+
+/// Sets the level of the module at `path` and the modules inside it
+pub fn set_module_level(path: &str, level: LevelFilter) -> Result<(), Error<'_>> {
+    let mut filter = filter();
+    filter.set_module(path, level)?;
+    set_filter(filter);
+    Ok(())
 }
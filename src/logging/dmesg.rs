@@ -0,0 +1,158 @@
+//! The kernel log buffer, the last `RECORDS` log records kept in memory.
+//!
+//! Records are numbered in the order they're logged and go into the slot
+//! `seq % RECORDS`, overwriting the record `RECORDS` before. Logging doesn't
+//! take a lock, so it works from interrupt handlers and the panic handler.
+//! Each slot works like a seqlock: its state tells which record it holds and
+//! whether that record is complete, readers copy the record out and check the
+//! state didn't change meanwhile.
+
+use crate::time::Uptime;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use log::Level;
+
+/// Number of records kept
+pub const RECORDS: usize = 128;
+/// Longest message kept, longer ones are cut off
+pub const MESSAGE_LEN: usize = 240;
+
+/// A record of the kernel log
+#[derive(Clone, Copy, Debug)]
+pub struct Record<'a> {
+    /// The number of the record, counting from 0 at boot
+    pub seq: u64,
+    /// Microseconds since boot when the record was logged, see `time::uptime_us`
+    pub timestamp: u64,
+    pub level: Level,
+    /// The source file and line logging the record
+    pub file: Option<&'static str>,
+    pub line: u32,
+    pub message: &'a str,
+}
+
+/// `[    1.234567] [INFO]@src/lib.rs:42> message`
+impl core::fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "[{}] [{}]@{}:{}> {}",
+            Uptime(self.timestamp),
+            self.level,
+            self.file.unwrap_or("none"),
+            self.line,
+            self.message
+        )
+    }
+}
+
+/// A record as it's stored, the message in place
+#[derive(Clone, Copy)]
+struct Stored {
+    timestamp: u64,
+    level: Level,
+    file: Option<&'static str>,
+    line: u32,
+    len: usize,
+    message: [u8; MESSAGE_LEN],
+}
+
+struct Slot {
+    /// `2 * seq + 1` while record `seq` is written, `2 * seq + 2` once it's complete,
+    /// 0 if the slot was never written
+    state: AtomicU64,
+    record: UnsafeCell<Stored>,
+}
+
+// SAFETY: the record is only read through `read`, which detects concurrent writes
+unsafe impl Sync for Slot {}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Slot = Slot {
+    state: AtomicU64::new(0),
+    record: UnsafeCell::new(Stored {
+        timestamp: 0,
+        level: Level::Error,
+        file: None,
+        line: 0,
+        len: 0,
+        message: [0; MESSAGE_LEN],
+    }),
+};
+static SLOTS: [Slot; RECORDS] = [EMPTY; RECORDS];
+/// The number of the next record
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// The number the next record will get, all records before it have been logged
+pub fn next_seq() -> u64 {
+    NEXT.load(Ordering::Acquire)
+}
+
+/// The number of the oldest record still kept
+pub fn first_seq() -> u64 {
+    next_seq().saturating_sub(RECORDS as u64)
+}
+
+/// Stores a record and returns its number, the message is cut off at `MESSAGE_LEN`
+pub fn push(
+    timestamp: u64,
+    level: Level,
+    file: Option<&'static str>,
+    line: u32,
+    message: &str,
+) -> u64 {
+    let message = crate::util::text::truncate(message, MESSAGE_LEN);
+    let seq = NEXT.fetch_add(1, Ordering::AcqRel);
+    let slot = &SLOTS[seq as usize % RECORDS];
+    slot.state.store(2 * seq + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    // SAFETY: readers detect the write through the state
+    let stored = unsafe { &mut *slot.record.get() };
+    stored.timestamp = timestamp;
+    stored.level = level;
+    stored.file = file;
+    stored.line = line;
+    stored.len = message.len();
+    stored.message[..message.len()].copy_from_slice(message.as_bytes());
+    slot.state.store(2 * seq + 2, Ordering::Release);
+    seq
+}
+
+/// Copies record `seq` out, `None` if it was overwritten or isn't complete
+fn read(seq: u64) -> Option<Stored> {
+    let slot = &SLOTS[seq as usize % RECORDS];
+    let state = slot.state.load(Ordering::Acquire);
+    if state != 2 * seq + 2 {
+        return None;
+    }
+    // a torn copy may hold an invalid `Level` or `Option`, so it stays uninitialized
+    // until the state shows it's complete
+    let stored =
+        unsafe { core::ptr::read_volatile(slot.record.get().cast::<MaybeUninit<Stored>>()) };
+    fence(Ordering::Acquire);
+    if slot.state.load(Ordering::Relaxed) != state {
+        return None;
+    }
+    // SAFETY: no write overlapped the copy
+    Some(unsafe { stored.assume_init() })
+}
+
+/// Calls `f` with the records from `from` up to but excluding `to`, oldest
+/// first. Records no longer kept or still being written are skipped.
+pub fn for_each<F: FnMut(&Record)>(from: u64, to: u64, mut f: F) {
+    for seq in from.max(first_seq())..to.min(next_seq()) {
+        if let Some(stored) = read(seq) {
+            let message = &stored.message[..stored.len];
+            f(&Record {
+                seq,
+                timestamp: stored.timestamp,
+                level: stored.level,
+                file: stored.file,
+                line: stored.line,
+                // SAFETY: copied from a `&str` cut at a character boundary
+                message: unsafe { core::str::from_utf8_unchecked(message) },
+            });
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Which records get logged, by level and module.
+//!
+//! A filter is written like `info,os::tty=trace,os::memory=off`: a bare level
+//! applies to every module, `path=level` to the module at `path` and the
+//! modules inside it, and a bare path logs everything from it. The most
+//! specific path wins.
+
+use arrayvec::{ArrayString, ArrayVec};
+use core::str::FromStr;
+use log::{LevelFilter, Metadata};
+
+/// Modules a filter can have a level for
+pub const MAX_MODULES: usize = 16;
+/// Longest module path a filter can hold
+pub const MAX_PATH: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error<'a> {
+    /// A level other than off, error, warn, info, debug or trace
+    Level(&'a str),
+    /// A module path longer than `MAX_PATH`
+    PathTooLong(&'a str),
+    /// More than `MAX_MODULES` modules
+    TooManyModules,
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, Error<'_>> {
+    LevelFilter::from_str(level.trim()).map_err(|_| Error::Level(level))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    default: LevelFilter,
+    modules: ArrayVec<(ArrayString<MAX_PATH>, LevelFilter), MAX_MODULES>,
+}
+
+impl Filter {
+    /// Logs everything up to `default`
+    pub const fn new(default: LevelFilter) -> Filter {
+        Filter {
+            default,
+            modules: ArrayVec::new_const(),
+        }
+    }
+
+    /// Parses a filter like `info,os::tty=trace`, see the module documentation.
+    /// Without a bare level, other modules log up to `LevelFilter::Debug`.
+    pub fn parse(spec: &str) -> Result<Filter, Error<'_>> {
+        let mut filter = Filter::default();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((path, level)) => filter.set_module(path.trim(), parse_level(level)?)?,
+                None => match parse_level(directive) {
+                    Ok(level) => filter.default = level,
+                    Err(_) => filter.set_module(directive, LevelFilter::Trace)?,
+                },
+            }
+        }
+        Ok(filter)
+    }
+
+    /// The level of modules the filter has no level for
+    pub fn default_level(&self) -> LevelFilter {
+        self.default
+    }
+
+    pub fn set_default_level(&mut self, level: LevelFilter) -> &mut Self {
+        self.default = level;
+        self
+    }
+
+    /// Sets the level of the module at `path` and the modules inside it
+    pub fn set_module<'a>(&mut self, path: &'a str, level: LevelFilter) -> Result<(), Error<'a>> {
+        if let Some(module) = self.modules.iter_mut().find(|(p, _)| p.as_str() == path) {
+            module.1 = level;
+            return Ok(());
+        }
+        let path_string = ArrayString::from(path).map_err(|_| Error::PathTooLong(path))?;
+        self.modules
+            .try_push((path_string, level))
+            .map_err(|_| Error::TooManyModules)
+    }
+
+    /// Forgets the level of the module at `path`, it gets the level of the
+    /// module around it again
+    pub fn remove_module(&mut self, path: &str) {
+        self.modules.retain(|(p, _)| p.as_str() != path);
+    }
+
+    /// The level of records from `target`, a module path
+    pub fn level(&self, target: &str) -> LevelFilter {
+        let inside = |path: &str| {
+            target == path || (target.starts_with(path) && target[path.len()..].starts_with("::"))
+        };
+        self.modules
+            .iter()
+            .filter(|(path, _)| inside(path))
+            .max_by_key(|(path, _)| path.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    /// The most verbose level of any module
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, Ord::max)
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(LevelFilter::Debug)
+    }
+}
@@ -0,0 +1,75 @@
+//! Where log records are written to, see `logging::add_sink`.
+
+use super::dmesg::Record;
+use crate::memio::{inb, outb};
+use crate::serial::{self, Com};
+use crate::time::Uptime;
+use core::fmt::Write;
+
+/// The port of QEMU's and Bochs' debug console
+const DEBUGCON_PORT: u16 = 0xE9;
+
+/// Something log records are written to.
+/// Sinks may be called from interrupt handlers and mustn't log themselves.
+pub trait Sink: Sync {
+    fn write(&self, record: &Record);
+}
+
+/// Writes records to a serial port with their source location, see `Record`'s `Display`
+pub struct Serial(pub Com);
+
+impl Sink for Serial {
+    fn write(&self, record: &Record) {
+        let _ = writeln!(serial::Writer(self.0), "{}", record);
+    }
+}
+
+/// Writes records to the TTY's log console, `[    1.234567] [INFO] message`.
+/// Records are skipped while the console is in use.
+pub struct Console;
+
+impl Sink for Console {
+    fn write(&self, record: &Record) {
+        if let Some(mut console) = crate::tty::log_console() {
+            let _ = crate::util::text::format_apply(
+                |s| {
+                    console.append_str(s.as_bytes());
+                    Ok(())
+                },
+                format_args!(
+                    "[{}] [{}] {}\n",
+                    Uptime(record.timestamp),
+                    record.level,
+                    record.message
+                ),
+            );
+            console.flush();
+        }
+    }
+}
+
+/// Writes records to QEMU's debug console, port 0xE9, like `Serial` does.
+/// Enable it with `-debugcon stdio` or `-debugcon file:log.txt`.
+pub struct DebugCon;
+
+impl DebugCon {
+    /// Whether the emulator has a debug console, reading its port gives 0xE9
+    pub fn is_present() -> bool {
+        unsafe { inb(DEBUGCON_PORT) == DEBUGCON_PORT as u8 }
+    }
+}
+
+impl Write for DebugCon {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &b in s.as_bytes() {
+            unsafe { outb(DEBUGCON_PORT, b) }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for DebugCon {
+    fn write(&self, record: &Record) {
+        let _ = writeln!(DebugCon, "{}", record);
+    }
+}
@@ -40,6 +40,12 @@ pub unsafe fn inb(port: u16) -> u8 {
     out
 }
 
+/// Waits a tiny amount of time (1-4 microseconds) by writing to the unused port 0x80.
+/// Old hardware, like the 8259 PIC, needs this between commands.
+pub fn io_wait() {
+    unsafe { outb(0x80, 0) }
+}
+
 /// Write a word to an MMIO `port`
 /// # Safety
 /// Validate that the port you write to is correct.
@@ -95,6 +101,22 @@ pub unsafe fn vwrite<T>(address: u64, val: &T) {
     }
 }
 
+/// Read a double word from `address` with a single 32-bit access.
+/// Device registers like the APIC's must not be accessed byte by byte.
+/// # Safety
+/// Validate that the address is correct and 4 byte aligned.
+pub unsafe fn vread32(address: u64) -> u32 {
+    (address as *const u32).read_volatile()
+}
+
+/// Write a double word to `address` with a single 32-bit access.
+/// Device registers like the APIC's must not be accessed byte by byte.
+/// # Safety
+/// Validate that the address is correct and 4 byte aligned.
+pub unsafe fn vwrite32(address: u64, val: u32) {
+    (address as *mut u32).write_volatile(val)
+}
+
 /// Write `count` bytes from `src` into `address`.
 /// # Safety
 /// Validate that the address you write to and the count are correct.
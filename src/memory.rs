@@ -0,0 +1,89 @@
+pub mod frame;
+pub mod heap;
+pub mod paging;
+
+pub use frame::Frame;
+
+/// Size of a page, and of a physical frame
+pub const PAGE_SIZE: usize = 4096;
+
+/// `boot.asm` identity maps the first GiB of physical memory with 2MiB pages,
+/// anything below this address may be accessed directly.
+pub const IDENTITY_MAPPED: usize = 1 << 30;
+
+extern "C" {
+    // defined in linker.ld
+    static kernel_start: u8;
+    static kernel_end: u8;
+    // defined in boot.asm
+    static p4_table: u8;
+    static p2_table: u8;
+    static stack_guard: u8;
+    static stack_bottom: u8;
+    static stack_top: u8;
+}
+
+/// Physical address range `(start, end)` of the loaded kernel image, including .bss
+pub fn kernel_image() -> (usize, usize) {
+    unsafe {
+        (
+            &kernel_start as *const u8 as usize,
+            &kernel_end as *const u8 as usize,
+        )
+    }
+}
+
+/// Physical address range `(start, end)` of the P4, P3 and P2 tables set up by `boot.asm`
+pub fn boot_page_tables() -> (usize, usize) {
+    unsafe {
+        (
+            &p4_table as *const u8 as usize,
+            &p2_table as *const u8 as usize + PAGE_SIZE,
+        )
+    }
+}
+
+/// Physical address range `(start, end)` of the boot stack `kmain` runs on
+pub fn boot_stack() -> (usize, usize) {
+    unsafe {
+        (
+            &stack_bottom as *const u8 as usize,
+            &stack_top as *const u8 as usize,
+        )
+    }
+}
+
+/// Physical address of the unmapped page below the boot stack
+pub fn boot_stack_guard() -> usize {
+    unsafe { &stack_guard as *const u8 as usize }
+}
+
+/// Rounds `addr` down to a multiple of `align`, which must be a power of two
+pub fn align_down(addr: usize, align: usize) -> usize {
+    addr & !(align - 1)
+}
+
+/// Rounds `addr` up to a multiple of `align`, which must be a power of two
+pub fn align_up(addr: usize, align: usize) -> usize {
+    align_down(addr + align - 1, align)
+}
+
+/// Initializes memory management from the bootloader's memory map
+pub fn init(info: &crate::multiboot::BootInfo) {
+    frame::init(info);
+    paging::init();
+    guard_boot_stack();
+    heap::init();
+}
+
+/// Unmaps the guard page below the boot stack, so that a stack overflow
+/// page faults (and double faults) instead of overwriting the boot page tables
+fn guard_boot_stack() {
+    let guard = boot_stack_guard();
+    let mut mapper = paging::mapper().lock();
+    let result = mapper.split(guard).and_then(|_| mapper.unmap(guard));
+    match result {
+        Ok(_) => log::info!("Unmapped the boot stack guard page at 0x{:x}", guard),
+        Err(e) => log::warn!("Failed to unmap the boot stack guard page: {:?}", e),
+    }
+}
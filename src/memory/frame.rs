@@ -0,0 +1,252 @@
+//! Bitmap based physical frame allocator.
+//!
+//! Every 4KiB frame of the first `MAX_PHYSICAL_MEMORY` bytes is represented by
+//! one bit, a set bit means the frame is in use or not RAM at all. The bitmap
+//! is a static in .bss, so it doesn't need any memory management itself.
+
+use super::{align_down, align_up, PAGE_SIZE};
+use crate::multiboot::BootInfo;
+
+/// Physical memory above this address is ignored
+pub const MAX_PHYSICAL_MEMORY: usize = 4 << 30;
+
+const FRAME_COUNT: usize = MAX_PHYSICAL_MEMORY / PAGE_SIZE;
+const BITMAP_WORDS: usize = FRAME_COUNT / 64;
+
+/// Everything below 1MiB is left alone, it's full of BIOS data, VGA memory and ROMs
+const LOW_MEMORY: usize = 0x10_0000;
+
+/// A 4KiB physical frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Frame(usize);
+
+impl Frame {
+    /// The frame containing the physical address `addr`
+    pub fn containing_address(addr: usize) -> Frame {
+        Frame(addr / PAGE_SIZE)
+    }
+
+    /// The frame with the given number
+    pub fn from_number(number: usize) -> Frame {
+        Frame(number)
+    }
+
+    /// The frame number, i.e. the start address divided by the frame size
+    pub fn number(&self) -> usize {
+        self.0
+    }
+
+    /// Physical address of the first byte of the frame
+    pub fn start_address(&self) -> usize {
+        self.0 * PAGE_SIZE
+    }
+}
+
+/// Frame counts, as returned by `stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Frames of available RAM according to the memory map
+    pub usable: usize,
+    /// Usable frames reserved at boot (kernel, boot information, ...)
+    pub reserved: usize,
+    /// Frames currently handed out
+    pub allocated: usize,
+    /// Frames ready to be allocated
+    pub free: usize,
+}
+
+pub struct FrameAllocator {
+    bitmap: [u64; BITMAP_WORDS],
+    /// word to start searching at
+    next: usize,
+    stats: Stats,
+}
+
+impl FrameAllocator {
+    const fn new() -> FrameAllocator {
+        FrameAllocator {
+            bitmap: [0; BITMAP_WORDS],
+            next: 0,
+            stats: Stats {
+                usable: 0,
+                reserved: 0,
+                allocated: 0,
+                free: 0,
+            },
+        }
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize) {
+        self.bitmap[frame / 64] |= 1 << (frame % 64);
+    }
+
+    fn set_free(&mut self, frame: usize) {
+        self.bitmap[frame / 64] &= !(1 << (frame % 64));
+    }
+
+    /// Marks the usable frames in `start..end` as free
+    fn add_region(&mut self, start: usize, end: usize) {
+        let end = end.min(MAX_PHYSICAL_MEMORY);
+        let start = align_up(start, PAGE_SIZE);
+        let end = align_down(end, PAGE_SIZE);
+        for frame in start / PAGE_SIZE..end / PAGE_SIZE {
+            if self.is_used(frame) {
+                self.set_free(frame);
+                self.stats.usable += 1;
+                self.stats.free += 1;
+            }
+        }
+    }
+
+    /// Marks the frames overlapping `start..end` as used, if they are free
+    fn reserve_region(&mut self, start: usize, end: usize) {
+        let end = align_up(end, PAGE_SIZE).min(MAX_PHYSICAL_MEMORY);
+        let start = align_down(start, PAGE_SIZE);
+        for frame in start / PAGE_SIZE..end / PAGE_SIZE {
+            if !self.is_used(frame) {
+                self.set_used(frame);
+                self.stats.reserved += 1;
+                self.stats.free -= 1;
+            }
+        }
+    }
+
+    /// Allocates a single frame
+    pub fn allocate(&mut self) -> Option<Frame> {
+        let words = (self.next..BITMAP_WORDS).chain(0..self.next);
+        for word in words {
+            if self.bitmap[word] != !0 {
+                let frame = word * 64 + (!self.bitmap[word]).trailing_zeros() as usize;
+                self.set_used(frame);
+                self.next = word;
+                self.stats.free -= 1;
+                self.stats.allocated += 1;
+                return Some(Frame(frame));
+            }
+        }
+        None
+    }
+
+    /// Allocates `count` physically contiguous frames which all lie below `limit`.
+    /// Returns the first frame.
+    pub fn allocate_contiguous(&mut self, count: usize, limit: usize) -> Option<Frame> {
+        let last = (limit.min(MAX_PHYSICAL_MEMORY) / PAGE_SIZE).checked_sub(count)?;
+        let mut start = 0;
+        while start <= last {
+            match (start..start + count).find(|&f| self.is_used(f)) {
+                Some(used) => start = used + 1,
+                None => {
+                    for frame in start..start + count {
+                        self.set_used(frame);
+                    }
+                    self.stats.free -= count;
+                    self.stats.allocated += count;
+                    return Some(Frame(start));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns a frame to the allocator, panics on a double free
+    pub fn deallocate(&mut self, frame: Frame) {
+        if frame.0 >= FRAME_COUNT || !self.is_used(frame.0) {
+            panic!(
+                "FrameAllocator::deallocate(0x{:x}): frame is not allocated",
+                frame.start_address()
+            );
+        }
+        self.set_free(frame.0);
+        self.next = self.next.min(frame.0 / 64);
+        self.stats.free += 1;
+        self.stats.allocated -= 1;
+    }
+
+    /// Current frame counts
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+static FRAME_ALLOCATOR: spin::Mutex<FrameAllocator> = spin::Mutex::new(FrameAllocator::new());
+
+/// Builds the free frame bitmap from the memory map.
+/// Panics if the bootloader didn't provide a memory map.
+pub fn init(info: &BootInfo) {
+    let mmap = match info.memory_map() {
+        Some(mmap) => mmap,
+        None => panic!("frame::init(): the bootloader didn't provide a memory map"),
+    };
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    allocator.bitmap.fill(!0);
+    allocator.stats = Stats::default();
+
+    for area in mmap.available_areas() {
+        if area.base as usize >= MAX_PHYSICAL_MEMORY {
+            log::warn!(
+                "Ignoring memory above 0x{:x}: 0x{:x}..0x{:x}",
+                MAX_PHYSICAL_MEMORY,
+                area.base,
+                area.end()
+            );
+            continue;
+        }
+        allocator.add_region(area.base as usize, area.end() as usize);
+    }
+
+    let (kernel_start, kernel_end) = super::kernel_image();
+    let (tables_start, tables_end) = super::boot_page_tables();
+    allocator.reserve_region(0, LOW_MEMORY);
+    allocator.reserve_region(kernel_start, kernel_end);
+    allocator.reserve_region(tables_start, tables_end);
+    allocator.reserve_region(info.start_address(), info.end_address());
+    for module in info.modules() {
+        allocator.reserve_region(module.start as usize, module.end as usize);
+    }
+
+    log::info!(
+        "Kernel image at 0x{:x}..0x{:x}, boot information at 0x{:x}..0x{:x}",
+        kernel_start,
+        kernel_end,
+        info.start_address(),
+        info.end_address()
+    );
+    drop(allocator);
+    log_stats();
+}
+
+/// Allocates a single frame
+pub fn allocate() -> Option<Frame> {
+    FRAME_ALLOCATOR.lock().allocate()
+}
+
+/// Allocates `count` physically contiguous frames below `limit`, returns the first one
+pub fn allocate_contiguous(count: usize, limit: usize) -> Option<Frame> {
+    FRAME_ALLOCATOR.lock().allocate_contiguous(count, limit)
+}
+
+/// Returns a frame to the allocator
+pub fn deallocate(frame: Frame) {
+    FRAME_ALLOCATOR.lock().deallocate(frame)
+}
+
+/// Current frame counts
+pub fn stats() -> Stats {
+    FRAME_ALLOCATOR.lock().stats()
+}
+
+/// Reports the frame counts over the serial log
+pub fn log_stats() {
+    let stats = stats();
+    log::info!(
+        "Physical memory: {} KiB usable, {} KiB reserved, {} KiB allocated, {} KiB free",
+        stats.usable * PAGE_SIZE / 1024,
+        stats.reserved * PAGE_SIZE / 1024,
+        stats.allocated * PAGE_SIZE / 1024,
+        stats.free * PAGE_SIZE / 1024
+    );
+}
@@ -0,0 +1,186 @@
+//! The kernel heap, backing the `alloc` crate.
+//!
+//! A first fit allocator over an address ordered list of free blocks, which
+//! are merged with their neighbours when freed. All block addresses and sizes
+//! are multiples of `BLOCK_ALIGN`, which is also the size of the free block
+//! header, so splitting a block never leaves an unusable sliver behind.
+
+use super::{align_up, PAGE_SIZE};
+use crate::cpu;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+/// Size of the kernel heap region
+pub const HEAP_SIZE: usize = 4 << 20;
+
+const BLOCK_ALIGN: usize = 16;
+
+#[repr(C, align(16))]
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<FreeBlock>(), BLOCK_ALIGN);
+
+/// Byte counts, as returned by `stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+}
+
+pub struct Heap {
+    /// sentinel, the list starts at `head.next`
+    head: FreeBlock,
+    start: usize,
+    size: usize,
+    used: usize,
+}
+
+// The heap exclusively owns the memory its pointers point to
+unsafe impl Send for Heap {}
+
+impl Heap {
+    const fn empty() -> Heap {
+        Heap {
+            head: FreeBlock {
+                size: 0,
+                next: ptr::null_mut(),
+            },
+            start: 0,
+            size: 0,
+            used: 0,
+        }
+    }
+
+    /// Hands the region `start..start + size` to the heap.
+    /// # Safety
+    /// The region must be mapped, writable, unused and `BLOCK_ALIGN` aligned.
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        self.start = start;
+        self.size = size - size % BLOCK_ALIGN;
+        self.used = 0;
+        self.head.next = ptr::null_mut();
+        self.insert(start, self.size);
+    }
+
+    /// The size a block for `layout` will have
+    fn block_size(layout: &Layout) -> usize {
+        align_up(layout.size().max(1), BLOCK_ALIGN)
+    }
+
+    /// Inserts the free block `addr..addr + size`, merging it with its neighbours
+    unsafe fn insert(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = &mut self.head;
+        while !(*prev).next.is_null() && ((*prev).next as usize) < addr {
+            prev = (*prev).next;
+        }
+        let next = (*prev).next;
+
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if !ptr::eq(prev, &self.head) && prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+
+    /// First fit allocation, returns null if no block is big enough
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = Self::block_size(&layout);
+        let align = layout.align().max(BLOCK_ALIGN);
+        unsafe {
+            let mut prev: *mut FreeBlock = &mut self.head;
+            while !(*prev).next.is_null() {
+                let block = (*prev).next;
+                let start = block as usize;
+                let end = start + (*block).size;
+                let alloc_start = align_up(start, align);
+                if alloc_start + size <= end {
+                    (*prev).next = (*block).next;
+                    if alloc_start + size < end {
+                        self.insert(alloc_start + size, end - alloc_start - size);
+                    }
+                    if alloc_start > start {
+                        self.insert(start, alloc_start - start);
+                    }
+                    self.used += size;
+                    return alloc_start as *mut u8;
+                }
+                prev = block;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// Returns the block at `ptr` to the free list
+    /// # Safety
+    /// `ptr` must have been returned by `allocate` with the same `layout`.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let size = Self::block_size(&layout);
+        let addr = ptr as usize;
+        if addr < self.start || addr + size > self.start + self.size {
+            panic!(
+                "Heap::deallocate(0x{:x}, {:?}): pointer is not on the heap",
+                addr, layout
+            );
+        }
+        self.used -= size;
+        self.insert(addr, size);
+    }
+
+    /// Current byte counts
+    pub fn stats(&self) -> Stats {
+        Stats {
+            size: self.size,
+            used: self.used,
+            free: self.size - self.used,
+        }
+    }
+}
+
+/// The heap behind a lock, which is only taken with interrupts disabled,
+/// so interrupt handlers may allocate too
+pub struct LockedHeap(spin::Mutex<Heap>);
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        cpu::without_interrupts(|| self.0.lock().allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        cpu::without_interrupts(|| self.0.lock().deallocate(ptr, layout))
+    }
+}
+
+#[global_allocator]
+static HEAP: LockedHeap = LockedHeap(spin::Mutex::new(Heap::empty()));
+
+/// Allocates the heap region from physically contiguous frames in the identity mapped area.
+/// Panics if there isn't enough memory.
+pub fn init() {
+    let frames = HEAP_SIZE / PAGE_SIZE;
+    let start = match super::frame::allocate_contiguous(frames, super::IDENTITY_MAPPED) {
+        Some(frame) => frame.start_address(),
+        None => panic!(
+            "heap::init(): no {} KiB of contiguous memory below 0x{:x}",
+            HEAP_SIZE / 1024,
+            super::IDENTITY_MAPPED
+        ),
+    };
+    cpu::without_interrupts(|| unsafe { HEAP.0.lock().init(start, HEAP_SIZE) });
+    log::info!("Kernel heap at 0x{:x}..0x{:x}", start, start + HEAP_SIZE);
+}
+
+/// Current byte counts
+pub fn stats() -> Stats {
+    cpu::without_interrupts(|| HEAP.0.lock().stats())
+}
@@ -0,0 +1,455 @@
+//! Access to the active 4-level page tables.
+//!
+//! `init` points the last P4 entry back at the P4 table itself. With this
+//! recursive entry every page table is reachable at a fixed virtual address,
+//! no matter where its frame lives, so tables for mappings outside the
+//! identity mapped first GiB can be created and edited from Rust.
+//! The top 512GiB of the address space are used up by the recursive mapping.
+
+use super::{frame, Frame, PAGE_SIZE};
+use crate::cpu;
+
+const ENTRY_COUNT: usize = 512;
+const RECURSIVE_INDEX: usize = 511;
+
+/// Page table entry flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Flags(u64);
+
+impl Flags {
+    pub const EMPTY: Flags = Flags(0);
+    pub const PRESENT: Flags = Flags(1 << 0);
+    pub const WRITABLE: Flags = Flags(1 << 1);
+    pub const USER: Flags = Flags(1 << 2);
+    pub const WRITE_THROUGH: Flags = Flags(1 << 3);
+    pub const NO_CACHE: Flags = Flags(1 << 4);
+    pub const ACCESSED: Flags = Flags(1 << 5);
+    pub const DIRTY: Flags = Flags(1 << 6);
+    pub const HUGE: Flags = Flags(1 << 7);
+    pub const GLOBAL: Flags = Flags(1 << 8);
+    pub const NO_EXECUTE: Flags = Flags(1 << 63);
+
+    /// Flags for memory mapped device registers
+    pub const MMIO: Flags =
+        Flags(Self::PRESENT.0 | Self::WRITABLE.0 | Self::NO_CACHE.0 | Self::WRITE_THROUGH.0);
+
+    /// The raw bits
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether all flags of `other` are set
+    pub fn contains(&self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for Flags {
+    type Output = Flags;
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+impl core::ops::BitAnd for Flags {
+    type Output = Flags;
+    fn bitand(self, rhs: Flags) -> Flags {
+        Flags(self.0 & rhs.0)
+    }
+}
+
+impl core::ops::BitOrAssign for Flags {
+    fn bitor_assign(&mut self, rhs: Flags) {
+        self.0 |= rhs.0
+    }
+}
+
+/// Bits of an entry holding the physical address
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+/// Bits of an entry holding flags
+const FLAGS_MASK: u64 = !ADDRESS_MASK;
+
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct Entry(u64);
+
+impl Entry {
+    pub fn is_present(&self) -> bool {
+        self.flags().contains(Flags::PRESENT)
+    }
+
+    pub fn flags(&self) -> Flags {
+        Flags(self.0 & FLAGS_MASK)
+    }
+
+    pub fn address(&self) -> usize {
+        (self.0 & ADDRESS_MASK) as usize
+    }
+
+    fn set(&mut self, address: usize, flags: Flags) {
+        self.0 = (address as u64 & ADDRESS_MASK) | flags.0;
+    }
+}
+
+#[repr(C, align(4096))]
+pub struct Table {
+    entries: [Entry; ENTRY_COUNT],
+}
+
+/// The size of a mapped page
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl PageSize {
+    /// Size in bytes
+    pub fn bytes(&self) -> usize {
+        match self {
+            PageSize::Size4KiB => PAGE_SIZE,
+            PageSize::Size2MiB => PAGE_SIZE * ENTRY_COUNT,
+            PageSize::Size1GiB => PAGE_SIZE * ENTRY_COUNT * ENTRY_COUNT,
+        }
+    }
+}
+
+/// Errors returned by the `Mapper`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The address isn't aligned to the page size
+    Misaligned,
+    /// The address isn't canonical, or lies in the recursive mapping
+    InvalidAddress,
+    /// There is already a mapping covering the page
+    AlreadyMapped,
+    /// The page isn't mapped
+    NotMapped,
+    /// The address is covered by a bigger page, see `Mapper::split`
+    HugePage(PageSize),
+    /// The frame allocator ran out of frames for a page table
+    OutOfFrames,
+    /// The CPU doesn't support the requested page size or flags
+    Unsupported,
+}
+
+/// A single mapping, as returned by `Mapper::lookup`
+#[derive(Clone, Copy, Debug)]
+pub struct Mapping {
+    /// Virtual address of the page
+    pub page: usize,
+    /// Physical address of the frame
+    pub frame: usize,
+    pub size: PageSize,
+    pub flags: Flags,
+}
+
+/// The table indices of a virtual address, from P4 to P1
+fn indices(virt: usize) -> [usize; 4] {
+    [
+        (virt >> 39) & 0x1FF,
+        (virt >> 30) & 0x1FF,
+        (virt >> 21) & 0x1FF,
+        (virt >> 12) & 0x1FF,
+    ]
+}
+
+/// Sign extends bit 47, making the address canonical
+fn canonical(addr: usize) -> usize {
+    ((addr << 16) as isize >> 16) as usize
+}
+
+/// The virtual address of the table reached by following `path` from the P4 table
+/// through the recursive entry, e.g. `&[]` is the P4 and `&[i]` the P3 table at P4 index `i`.
+fn table_address(path: &[usize]) -> usize {
+    let mut addr = 0;
+    for i in 0..4 - path.len() {
+        addr |= RECURSIVE_INDEX << (39 - 9 * i);
+    }
+    for (i, &index) in path.iter().enumerate() {
+        addr |= index << (39 - 9 * (4 - path.len() + i));
+    }
+    canonical(addr)
+}
+
+/// Handle to the active page tables, obtained through `mapper()`
+pub struct Mapper {
+    huge_1gib: bool,
+}
+
+impl Mapper {
+    /// # Safety
+    /// `path` must lead to a present, non huge table entry
+    unsafe fn table(&mut self, path: &[usize]) -> &mut Table {
+        &mut *(table_address(path) as *mut Table)
+    }
+
+    /// Walks to the table at `depth` (0 = P4) for `virt`, optionally creating missing tables.
+    fn walk(
+        &mut self,
+        virt: usize,
+        depth: usize,
+        create: bool,
+        user: bool,
+    ) -> Result<&mut Table, Error> {
+        let idx = indices(virt);
+        for level in 0..depth {
+            let entry = unsafe { self.table(&idx[..level]) }.entries[idx[level]];
+            if !entry.is_present() {
+                if !create {
+                    return Err(Error::NotMapped);
+                }
+                let frame = frame::allocate().ok_or(Error::OutOfFrames)?;
+                let mut flags = Flags::PRESENT | Flags::WRITABLE;
+                if user {
+                    flags |= Flags::USER;
+                }
+                let table = unsafe { self.table(&idx[..level]) };
+                table.entries[idx[level]].set(frame.start_address(), flags);
+                let new = table_address(&idx[..=level]);
+                cpu::invlpg(new);
+                unsafe { (new as *mut Table).write_bytes(0, 1) };
+            } else if entry.flags().contains(Flags::HUGE) {
+                return Err(Error::HugePage(match level {
+                    1 => PageSize::Size1GiB,
+                    _ => PageSize::Size2MiB,
+                }));
+            } else if user && !entry.flags().contains(Flags::USER) {
+                let table = unsafe { self.table(&idx[..level]) };
+                table.entries[idx[level]].set(entry.address(), entry.flags() | Flags::USER);
+            }
+        }
+        Ok(unsafe { self.table(&idx[..depth]) })
+    }
+
+    /// Checks the address and returns the table depth holding entries of `size`
+    fn check(&self, virt: usize, size: PageSize) -> Result<usize, Error> {
+        if canonical(virt) != virt || indices(virt)[0] == RECURSIVE_INDEX {
+            return Err(Error::InvalidAddress);
+        }
+        if virt % size.bytes() != 0 {
+            return Err(Error::Misaligned);
+        }
+        Ok(match size {
+            PageSize::Size1GiB if !self.huge_1gib => return Err(Error::Unsupported),
+            PageSize::Size1GiB => 1,
+            PageSize::Size2MiB => 2,
+            PageSize::Size4KiB => 3,
+        })
+    }
+
+    /// Finds the page containing `virt`
+    pub fn lookup(&mut self, virt: usize) -> Option<Mapping> {
+        if canonical(virt) != virt {
+            return None;
+        }
+        let idx = indices(virt);
+        for level in 0..4 {
+            let entry = unsafe { self.table(&idx[..level]) }.entries[idx[level]];
+            if !entry.is_present() {
+                return None;
+            }
+            let size = match level {
+                1 if entry.flags().contains(Flags::HUGE) => PageSize::Size1GiB,
+                2 if entry.flags().contains(Flags::HUGE) => PageSize::Size2MiB,
+                3 => PageSize::Size4KiB,
+                _ => continue,
+            };
+            return Some(Mapping {
+                page: virt & !(size.bytes() - 1),
+                frame: entry.address(),
+                size,
+                flags: entry.flags(),
+            });
+        }
+        None
+    }
+
+    /// Translates a virtual into a physical address
+    pub fn translate(&mut self, virt: usize) -> Option<usize> {
+        self.lookup(virt)
+            .map(|m| m.frame + (virt & (m.size.bytes() - 1)))
+    }
+
+    /// Maps the page at `virt` to the frame at `phys`.
+    /// `PRESENT` is implied, `HUGE` is set automatically for 2MiB and 1GiB pages.
+    pub fn map(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        size: PageSize,
+        flags: Flags,
+    ) -> Result<(), Error> {
+        let depth = self.check(virt, size)?;
+        if phys % size.bytes() != 0 {
+            return Err(Error::Misaligned);
+        }
+        if flags.contains(Flags::NO_EXECUTE) && !cpu::has_nx() {
+            return Err(Error::Unsupported);
+        }
+        let mut flags = flags | Flags::PRESENT;
+        if size != PageSize::Size4KiB {
+            flags |= Flags::HUGE;
+        }
+        let table = self.walk(virt, depth, true, flags.contains(Flags::USER))?;
+        let entry = &mut table.entries[indices(virt)[depth]];
+        if entry.is_present() {
+            return Err(Error::AlreadyMapped);
+        }
+        entry.set(phys, flags);
+        cpu::invlpg(virt);
+        Ok(())
+    }
+
+    /// Unmaps the page starting at `virt`, returning the frame it was mapped to.
+    /// Page tables which become empty are not freed.
+    pub fn unmap(&mut self, virt: usize) -> Result<Mapping, Error> {
+        let mapping = self.lookup(virt).ok_or(Error::NotMapped)?;
+        if mapping.page != virt {
+            return Err(Error::HugePage(mapping.size));
+        }
+        let depth = self.check(virt, mapping.size)?;
+        let table = self.walk(virt, depth, false, false)?;
+        table.entries[indices(virt)[depth]].set(0, Flags::EMPTY);
+        Self::flush_page(&mapping);
+        Ok(mapping)
+    }
+
+    /// Replaces the flags of the page starting at `virt`, keeping the frame
+    pub fn set_flags(&mut self, virt: usize, flags: Flags) -> Result<(), Error> {
+        let mapping = self.lookup(virt).ok_or(Error::NotMapped)?;
+        if mapping.page != virt {
+            return Err(Error::HugePage(mapping.size));
+        }
+        if flags.contains(Flags::NO_EXECUTE) && !cpu::has_nx() {
+            return Err(Error::Unsupported);
+        }
+        let depth = self.check(virt, mapping.size)?;
+        let mut flags = flags | Flags::PRESENT;
+        if mapping.size != PageSize::Size4KiB {
+            flags |= Flags::HUGE;
+        }
+        let table = self.walk(virt, depth, false, flags.contains(Flags::USER))?;
+        table.entries[indices(virt)[depth]].set(mapping.frame, flags);
+        Self::flush_page(&mapping);
+        Ok(())
+    }
+
+    /// Splits the huge page containing `virt` into 512 pages of the next smaller size,
+    /// with the same flags. Does nothing if `virt` is mapped with 4KiB pages.
+    pub fn split(&mut self, virt: usize) -> Result<(), Error> {
+        let mapping = self.lookup(virt).ok_or(Error::NotMapped)?;
+        let (depth, smaller) = match mapping.size {
+            PageSize::Size4KiB => return Ok(()),
+            PageSize::Size2MiB => (2, PageSize::Size4KiB),
+            PageSize::Size1GiB => (1, PageSize::Size2MiB),
+        };
+        // The new table is filled before it's swapped in, so that the range stays
+        // mapped all the time. It has to be reachable without the recursive
+        // mapping for that, the identity mapped area is.
+        let frame =
+            frame::allocate_contiguous(1, super::IDENTITY_MAPPED).ok_or(Error::OutOfFrames)?;
+        let mut flags = mapping.flags;
+        if smaller == PageSize::Size4KiB {
+            flags = Flags(flags.0 & !Flags::HUGE.0);
+        }
+        let table = unsafe { &mut *(frame.start_address() as *mut Table) };
+        for (i, entry) in table.entries.iter_mut().enumerate() {
+            entry.set(mapping.frame + i * smaller.bytes(), flags);
+        }
+
+        let idx = indices(mapping.page);
+        let parent = unsafe { self.table(&idx[..depth]) };
+        parent.entries[idx[depth]].set(
+            frame.start_address(),
+            Flags::PRESENT | Flags::WRITABLE | (mapping.flags & Flags::USER),
+        );
+        Self::flush_page(&mapping);
+        Ok(())
+    }
+
+    /// Identity maps the physical range `phys..phys + len` with 4KiB pages.
+    /// Pages which are already identity mapped, e.g. in the first GiB, are left alone.
+    pub fn identity_map(&mut self, phys: usize, len: usize, flags: Flags) -> Result<(), Error> {
+        let start = super::align_down(phys, PAGE_SIZE);
+        let end = super::align_up(phys + len, PAGE_SIZE);
+        for page in (start..end).step_by(PAGE_SIZE) {
+            match self.lookup(page) {
+                Some(m) if m.frame + (page - m.page) == page => continue,
+                Some(_) => return Err(Error::AlreadyMapped),
+                None => self.map(page, page, PageSize::Size4KiB, flags)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_page(mapping: &Mapping) {
+        if mapping.size == PageSize::Size4KiB {
+            cpu::invlpg(mapping.page);
+        } else {
+            flush_all();
+        }
+    }
+}
+
+/// Flushes the whole TLB, except for global pages
+pub fn flush_all() {
+    unsafe { cpu::write_cr3(cpu::read_cr3()) }
+}
+
+/// Flushes the TLB entry of the page containing `virt`
+pub fn flush(virt: usize) {
+    cpu::invlpg(virt)
+}
+
+static MAPPER: spin::Mutex<Mapper> = spin::Mutex::new(Mapper { huge_1gib: false });
+
+/// Installs the recursive P4 entry and enables the no-execute bit
+pub fn init() {
+    let p4 = (cpu::read_cr3() & ADDRESS_MASK) as usize;
+    // the boot P4 table lives in the identity mapped kernel image
+    let table = unsafe { &mut *(p4 as *mut Table) };
+    table.entries[RECURSIVE_INDEX].set(p4, Flags::PRESENT | Flags::WRITABLE);
+    flush_all();
+
+    if cpu::has_nx() {
+        unsafe { cpu::wrmsr(cpu::MSR_EFER, cpu::rdmsr(cpu::MSR_EFER) | cpu::EFER_NXE) };
+    }
+    let mut mapper = MAPPER.lock();
+    mapper.huge_1gib = cpu::has_1gib_pages();
+    log::info!(
+        "Paging: P4 table at 0x{:x}, NX {}, 1GiB pages {}",
+        p4,
+        if cpu::has_nx() {
+            "supported"
+        } else {
+            "unsupported"
+        },
+        if mapper.huge_1gib {
+            "supported"
+        } else {
+            "unsupported"
+        }
+    );
+}
+
+/// Handle to the active page tables
+pub fn mapper() -> &'static spin::Mutex<Mapper> {
+    &MAPPER
+}
+
+/// Allocates a frame and maps it at `virt` with a 4KiB page
+pub fn map_new(virt: usize, flags: Flags) -> Result<Frame, Error> {
+    let frame = frame::allocate().ok_or(Error::OutOfFrames)?;
+    match MAPPER
+        .lock()
+        .map(virt, frame.start_address(), PageSize::Size4KiB, flags)
+    {
+        Ok(()) => Ok(frame),
+        Err(e) => {
+            frame::deallocate(frame);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,537 @@
+//! Parser for the Multiboot2 boot information structure.
+//!
+//! The bootloader leaves the physical address of the structure in `ebx` and
+//! the magic value in `eax`; `boot.asm` forwards both to `kmain`, which hands
+//! them to `init`. Every access is bounds-checked against the `total_size`
+//! field of the structure, a malformed tag ends the tag iteration instead of
+//! reading past the end.
+//!
+//! The structure lives inside the identity mapped first GiB, so the physical
+//! address is directly usable as a pointer.
+
+use crate::util::bytes::{checksum_ok, read, read_cstr};
+
+/// The value the bootloader puts into `eax` before jumping to the kernel
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+/// Errors which can occur while loading the boot information
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The magic value does not match `BOOTLOADER_MAGIC`
+    BadMagic(u32),
+    /// The structure pointer is null
+    Null,
+    /// The structure is not 8 byte aligned
+    Misaligned(usize),
+    /// The `total_size` field is too small to hold the fixed part and an end tag
+    BadSize(u32),
+}
+
+/// Tag type numbers as defined by the Multiboot2 specification
+pub mod tag_type {
+    pub const END: u32 = 0;
+    pub const COMMAND_LINE: u32 = 1;
+    pub const BOOT_LOADER_NAME: u32 = 2;
+    pub const MODULE: u32 = 3;
+    pub const BASIC_MEMINFO: u32 = 4;
+    pub const BOOTDEV: u32 = 5;
+    pub const MEMORY_MAP: u32 = 6;
+    pub const FRAMEBUFFER: u32 = 8;
+    pub const ELF_SECTIONS: u32 = 9;
+    pub const APM: u32 = 10;
+    pub const ACPI_OLD: u32 = 14;
+    pub const ACPI_NEW: u32 = 15;
+    pub const LOAD_BASE_ADDR: u32 = 21;
+}
+
+/// The whole boot information structure
+#[derive(Clone, Copy)]
+pub struct BootInfo {
+    bytes: &'static [u8],
+}
+
+impl BootInfo {
+    /// Validates and wraps the boot information at `address`.
+    /// # Safety
+    /// `address` must be the pointer handed over by a Multiboot2 compliant
+    /// bootloader, and the memory must stay untouched for as long as the
+    /// `BootInfo` is used.
+    pub unsafe fn load(address: usize) -> Result<BootInfo, Error> {
+        if address == 0 {
+            return Err(Error::Null);
+        }
+        if address % 8 != 0 {
+            return Err(Error::Misaligned(address));
+        }
+        let total_size = (address as *const u32).read();
+        // fixed part (8 bytes) + end tag (8 bytes)
+        if total_size < 16 {
+            return Err(Error::BadSize(total_size));
+        }
+        Ok(BootInfo {
+            bytes: core::slice::from_raw_parts(address as *const u8, total_size as usize),
+        })
+    }
+
+    /// Physical address of the first byte of the structure
+    pub fn start_address(&self) -> usize {
+        self.bytes.as_ptr() as usize
+    }
+
+    /// Physical address of the first byte past the structure
+    pub fn end_address(&self) -> usize {
+        self.start_address() + self.bytes.len()
+    }
+
+    /// Size of the structure in bytes, including all tags
+    pub fn total_size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Iterates over all tags, stopping at the end tag or at the first malformed one
+    pub fn tags(&self) -> TagIter {
+        TagIter {
+            bytes: self.bytes,
+            offset: 8,
+        }
+    }
+
+    /// The first tag with the given type
+    pub fn tag(&self, typ: u32) -> Option<Tag> {
+        self.tags().find(|t| t.typ() == typ)
+    }
+
+    /// The kernel command line, as set in `grub.cfg`
+    pub fn command_line(&self) -> Option<&'static str> {
+        self.tag(tag_type::COMMAND_LINE)
+            .and_then(|t| read_cstr(t.payload()))
+    }
+
+    /// The name of the bootloader, e.g. `GRUB 2.04`
+    pub fn boot_loader_name(&self) -> Option<&'static str> {
+        self.tag(tag_type::BOOT_LOADER_NAME)
+            .and_then(|t| read_cstr(t.payload()))
+    }
+
+    /// Amount of lower and upper memory in KiB
+    pub fn basic_memory_info(&self) -> Option<(u32, u32)> {
+        let payload = self.tag(tag_type::BASIC_MEMINFO)?.payload();
+        Some((read(payload, 0)?, read(payload, 4)?))
+    }
+
+    /// The memory map provided by the firmware
+    pub fn memory_map(&self) -> Option<MemoryMap> {
+        let payload = self.tag(tag_type::MEMORY_MAP)?.payload();
+        let entry_size: u32 = read(payload, 0)?;
+        let entry_version: u32 = read(payload, 4)?;
+        // an entry must at least hold base, length and type
+        if entry_size < 20 {
+            return None;
+        }
+        Some(MemoryMap {
+            entry_size: entry_size as usize,
+            entry_version,
+            entries: &payload[8..],
+        })
+    }
+
+    /// All boot modules loaded by the bootloader
+    pub fn modules(&self) -> impl Iterator<Item = Module> {
+        self.tags()
+            .filter(|t| t.typ() == tag_type::MODULE)
+            .filter_map(|t| {
+                let payload = t.payload();
+                Some(Module {
+                    start: read(payload, 0)?,
+                    end: read(payload, 4)?,
+                    string: read_cstr(payload.get(8..)?).unwrap_or(""),
+                })
+            })
+    }
+
+    /// Information about the framebuffer set up by the bootloader
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let payload = self.tag(tag_type::FRAMEBUFFER)?.payload();
+        let kind = match read::<u8>(payload, 21)? {
+            0 => {
+                let count: u16 = read(payload, 24)?;
+                let palette = payload.get(26..26 + count as usize * 3)?;
+                FramebufferKind::Indexed { palette }
+            }
+            1 => FramebufferKind::Rgb {
+                red: ColorField {
+                    position: read(payload, 24)?,
+                    size: read(payload, 25)?,
+                },
+                green: ColorField {
+                    position: read(payload, 26)?,
+                    size: read(payload, 27)?,
+                },
+                blue: ColorField {
+                    position: read(payload, 28)?,
+                    size: read(payload, 29)?,
+                },
+            },
+            2 => FramebufferKind::EgaText,
+            other => FramebufferKind::Unknown(other),
+        };
+        Some(Framebuffer {
+            address: read(payload, 0)?,
+            pitch: read(payload, 8)?,
+            width: read(payload, 12)?,
+            height: read(payload, 16)?,
+            bpp: read(payload, 20)?,
+            kind,
+        })
+    }
+
+    /// The section headers of the kernel ELF image
+    pub fn elf_sections(&self) -> Option<ElfSections> {
+        let payload = self.tag(tag_type::ELF_SECTIONS)?.payload();
+        let count: u32 = read(payload, 0)?;
+        let entry_size: u32 = read(payload, 4)?;
+        let shndx: u32 = read(payload, 8)?;
+        if entry_size < 64 {
+            return None;
+        }
+        let headers = payload.get(12..12 + count as usize * entry_size as usize)?;
+        Some(ElfSections {
+            headers,
+            entry_size: entry_size as usize,
+            shndx: shndx as usize,
+        })
+    }
+
+    /// The ACPI root system description pointer, preferring the ACPI 2.0+ copy
+    pub fn rsdp(&self) -> Option<Rsdp> {
+        self.tag(tag_type::ACPI_NEW)
+            .or_else(|| self.tag(tag_type::ACPI_OLD))
+            .and_then(|t| Rsdp::parse(t.payload()))
+    }
+}
+
+// The structure is never written to after boot
+unsafe impl Send for BootInfo {}
+unsafe impl Sync for BootInfo {}
+
+/// A single tag, without its 8 byte header
+#[derive(Clone, Copy)]
+pub struct Tag {
+    typ: u32,
+    payload: &'static [u8],
+}
+
+impl Tag {
+    /// The tag type, see `tag_type`
+    pub fn typ(&self) -> u32 {
+        self.typ
+    }
+
+    /// The tag contents following the type and size fields
+    pub fn payload(&self) -> &'static [u8] {
+        self.payload
+    }
+}
+
+/// Iterator over the tags of a `BootInfo`
+pub struct TagIter {
+    bytes: &'static [u8],
+    offset: usize,
+}
+
+impl Iterator for TagIter {
+    type Item = Tag;
+
+    fn next(&mut self) -> Option<Tag> {
+        let typ: u32 = read(self.bytes, self.offset)?;
+        let size: u32 = read(self.bytes, self.offset + 4)?;
+        let end = self.offset.checked_add(size as usize)?;
+        if typ == tag_type::END || size < 8 || end > self.bytes.len() {
+            self.offset = self.bytes.len();
+            return None;
+        }
+        let tag = Tag {
+            typ,
+            payload: &self.bytes[self.offset + 8..end],
+        };
+        // tags are padded to 8 bytes
+        self.offset = (end + 7) & !7;
+        Some(tag)
+    }
+}
+
+/// Type of a memory map area
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryAreaType {
+    Available,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    Defective,
+    Unknown(u32),
+}
+
+impl From<u32> for MemoryAreaType {
+    fn from(t: u32) -> Self {
+        match t {
+            1 => MemoryAreaType::Available,
+            2 => MemoryAreaType::Reserved,
+            3 => MemoryAreaType::AcpiReclaimable,
+            4 => MemoryAreaType::AcpiNvs,
+            5 => MemoryAreaType::Defective,
+            t => MemoryAreaType::Unknown(t),
+        }
+    }
+}
+
+/// A physical memory region from the memory map
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryArea {
+    pub base: u64,
+    pub length: u64,
+    pub typ: MemoryAreaType,
+}
+
+impl MemoryArea {
+    /// The first address past the area
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.length)
+    }
+}
+
+/// The memory map tag
+#[derive(Clone, Copy)]
+pub struct MemoryMap {
+    entry_size: usize,
+    entry_version: u32,
+    entries: &'static [u8],
+}
+
+impl MemoryMap {
+    /// Version of the entry format, currently always 0
+    pub fn entry_version(&self) -> u32 {
+        self.entry_version
+    }
+
+    /// All memory areas, regardless of type
+    pub fn areas(&self) -> impl Iterator<Item = MemoryArea> {
+        let entries = self.entries;
+        let entry_size = self.entry_size;
+        (0..entries.len() / entry_size)
+            .map(move |i| i * entry_size)
+            .filter_map(move |off| {
+                Some(MemoryArea {
+                    base: read(entries, off)?,
+                    length: read(entries, off + 8)?,
+                    typ: MemoryAreaType::from(read::<u32>(entries, off + 16)?),
+                })
+            })
+    }
+
+    /// Only the areas usable as general purpose RAM
+    pub fn available_areas(&self) -> impl Iterator<Item = MemoryArea> {
+        self.areas().filter(|a| a.typ == MemoryAreaType::Available)
+    }
+}
+
+/// A boot module loaded by the bootloader
+#[derive(Clone, Copy, Debug)]
+pub struct Module {
+    /// Physical start address
+    pub start: u32,
+    /// Physical end address (exclusive)
+    pub end: u32,
+    /// The string given to the `module2` command
+    pub string: &'static str,
+}
+
+/// Position and width of a color channel in an RGB framebuffer pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColorField {
+    pub position: u8,
+    pub size: u8,
+}
+
+/// Pixel format of the framebuffer
+#[derive(Clone, Copy, Debug)]
+pub enum FramebufferKind {
+    /// Palette based, each palette entry is 3 bytes of red, green, blue
+    Indexed {
+        palette: &'static [u8],
+    },
+    /// Direct RGB color
+    Rgb {
+        red: ColorField,
+        green: ColorField,
+        blue: ColorField,
+    },
+    /// EGA text mode, `width` and `height` are in characters
+    EgaText,
+    Unknown(u8),
+}
+
+/// The framebuffer tag
+#[derive(Clone, Copy, Debug)]
+pub struct Framebuffer {
+    /// Physical address of the framebuffer
+    pub address: u64,
+    /// Bytes per line
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel
+    pub bpp: u8,
+    pub kind: FramebufferKind,
+}
+
+/// The ELF sections tag
+#[derive(Clone, Copy)]
+pub struct ElfSections {
+    headers: &'static [u8],
+    entry_size: usize,
+    shndx: usize,
+}
+
+impl ElfSections {
+    /// Iterates over all section headers
+    pub fn sections(&self) -> impl Iterator<Item = ElfSection> {
+        let headers = self.headers;
+        let entry_size = self.entry_size;
+        (0..headers.len() / entry_size)
+            .filter_map(move |i| ElfSection::parse(&headers[i * entry_size..(i + 1) * entry_size]))
+    }
+
+    /// The name of a section, looked up in the loaded section name string table
+    pub fn name(&self, section: &ElfSection) -> Option<&'static str> {
+        let strtab = self.sections().nth(self.shndx)?;
+        if strtab.addr == 0 || section.name_index as u64 >= strtab.size {
+            return None;
+        }
+        // SAFETY: the bootloader loads the string table at `addr`
+        let table =
+            unsafe { core::slice::from_raw_parts(strtab.addr as *const u8, strtab.size as usize) };
+        read_cstr(&table[section.name_index as usize..])
+    }
+}
+
+/// A single ELF64 section header
+#[derive(Clone, Copy, Debug)]
+pub struct ElfSection {
+    pub name_index: u32,
+    pub typ: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub size: u64,
+}
+
+impl ElfSection {
+    /// `SHF_WRITE`
+    pub const WRITABLE: u64 = 0x1;
+    /// `SHF_ALLOC`
+    pub const ALLOCATED: u64 = 0x2;
+    /// `SHF_EXECINSTR`
+    pub const EXECUTABLE: u64 = 0x4;
+
+    fn parse(header: &[u8]) -> Option<ElfSection> {
+        Some(ElfSection {
+            name_index: read(header, 0)?,
+            typ: read(header, 4)?,
+            flags: read(header, 8)?,
+            addr: read(header, 16)?,
+            size: read(header, 32)?,
+        })
+    }
+
+    /// Whether the section occupies memory at runtime
+    pub fn is_allocated(&self) -> bool {
+        self.flags & Self::ALLOCATED != 0
+    }
+}
+
+/// The ACPI root system description pointer
+#[derive(Clone, Copy, Debug)]
+pub struct Rsdp {
+    /// 0 for ACPI 1.0, 2 for ACPI 2.0 and later
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    /// Physical address of the RSDT
+    pub rsdt_address: u32,
+    /// Physical address of the XSDT, only present for revision 2+
+    pub xsdt_address: Option<u64>,
+}
+
+impl Rsdp {
+    fn parse(bytes: &[u8]) -> Option<Rsdp> {
+        if bytes.get(..8)? != b"RSD PTR " {
+            return None;
+        }
+        if !checksum_ok(bytes.get(..20)?) {
+            return None;
+        }
+        let revision: u8 = read(bytes, 15)?;
+        let xsdt_address = if revision >= 2 {
+            let length: u32 = read(bytes, 20)?;
+            if !checksum_ok(bytes.get(..length as usize)?) {
+                return None;
+            }
+            Some(read(bytes, 24)?)
+        } else {
+            None
+        };
+        Some(Rsdp {
+            revision,
+            oem_id: read(bytes, 9)?,
+            rsdt_address: read(bytes, 16)?,
+            xsdt_address,
+        })
+    }
+}
+
+static BOOT_INFO: spin::Once<BootInfo> = spin::Once::new();
+
+/// Validates the values handed over by the bootloader and stores the boot information.
+/// Panics if `magic` is wrong or the structure is malformed.
+pub fn init(magic: u32, address: usize) -> &'static BootInfo {
+    if magic != BOOTLOADER_MAGIC {
+        panic!(
+            "multiboot::init(0x{:x}, 0x{:x}): {:?}",
+            magic,
+            address,
+            Error::BadMagic(magic)
+        );
+    }
+    let info = match unsafe { BootInfo::load(address) } {
+        Ok(info) => info,
+        Err(e) => panic!("multiboot::init(0x{:x}, 0x{:x}): {:?}", magic, address, e),
+    };
+    let info = BOOT_INFO.call_once(|| info);
+
+    log::info!(
+        "Multiboot2 information at 0x{:x}..0x{:x}, loaded by {}",
+        info.start_address(),
+        info.end_address(),
+        info.boot_loader_name().unwrap_or("an unknown bootloader")
+    );
+    if let Some(cmdline) = info.command_line() {
+        log::info!("Kernel command line: '{}'", cmdline);
+    }
+    if let Some(mmap) = info.memory_map() {
+        for area in mmap.areas() {
+            log::debug!(
+                "Memory area 0x{:016x}..0x{:016x} {:?}",
+                area.base,
+                area.end(),
+                area.typ
+            );
+        }
+    }
+    info
+}
+
+/// The boot information, panics if `init` hasn't been called yet
+pub fn info() -> &'static BootInfo {
+    match BOOT_INFO.get() {
+        Some(info) => info,
+        None => panic!("multiboot::info(): called before multiboot::init"),
+    }
+}
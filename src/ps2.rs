@@ -0,0 +1,224 @@
+//! The i8042 PS/2 controller.
+//!
+//! `init` runs the controller and port self-tests and enables the working
+//! ports with their interrupts. Scancode translation is turned off, the
+//! keyboard driver picks the scancode set itself.
+
+pub mod keyboard;
+
+use crate::memio::{inb, io_wait, outb};
+
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64;
+const COMMAND: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_SECOND: u8 = 0xA7;
+const CMD_ENABLE_SECOND: u8 = 0xA8;
+const CMD_TEST_SECOND: u8 = 0xA9;
+const CMD_TEST_CONTROLLER: u8 = 0xAA;
+const CMD_TEST_FIRST: u8 = 0xAB;
+const CMD_DISABLE_FIRST: u8 = 0xAD;
+const CMD_ENABLE_FIRST: u8 = 0xAE;
+const CMD_WRITE_SECOND: u8 = 0xD4;
+
+/// Configuration byte: first port interrupt
+pub const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+/// Configuration byte: second port interrupt
+pub const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+/// Configuration byte: first port clock disabled
+pub const CONFIG_FIRST_CLOCK_DISABLED: u8 = 1 << 4;
+/// Configuration byte: second port clock disabled
+pub const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+/// Configuration byte: translation of scancode set 2 to set 1 on the first port
+pub const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const TEST_CONTROLLER_OK: u8 = 0x55;
+const TEST_PORT_OK: u8 = 0x00;
+
+/// Polls of the status register before giving up, each takes about a microsecond
+const TIMEOUT: usize = 100_000;
+
+/// A port of the controller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    First,
+    Second,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The controller or device didn't respond in time
+    Timeout,
+    /// The controller self-test returned this instead of 0x55
+    ControllerTest(u8),
+    /// The port test returned this error code
+    PortTest(Port, u8),
+    /// The device sent an unexpected reply to a command
+    UnexpectedReply(u8),
+}
+
+/// The ports which passed their self-test
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ports {
+    pub first: bool,
+    pub second: bool,
+}
+
+fn status() -> u8 {
+    unsafe { inb(STATUS) }
+}
+
+fn wait_for(mask: u8, set: bool) -> Result<(), Error> {
+    for _ in 0..TIMEOUT {
+        if (status() & mask != 0) == set {
+            return Ok(());
+        }
+        io_wait();
+    }
+    Err(Error::Timeout)
+}
+
+/// Whether a byte is waiting in the output buffer
+pub fn has_data() -> bool {
+    status() & STATUS_OUTPUT_FULL != 0
+}
+
+/// Reads the output buffer without checking whether it's full, for interrupt handlers
+pub fn read_data() -> u8 {
+    unsafe { inb(DATA) }
+}
+
+/// Waits for a byte in the output buffer and reads it
+pub fn read() -> Result<u8, Error> {
+    wait_for(STATUS_OUTPUT_FULL, true)?;
+    Ok(read_data())
+}
+
+/// Discards all bytes waiting in the output buffer
+pub fn flush() {
+    while has_data() {
+        read_data();
+    }
+}
+
+/// Sends a command to the controller itself
+pub fn command(cmd: u8) -> Result<(), Error> {
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { outb(COMMAND, cmd) };
+    Ok(())
+}
+
+/// Sends a command to the controller which has a parameter
+pub fn command_with_data(cmd: u8, data: u8) -> Result<(), Error> {
+    command(cmd)?;
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { outb(DATA, data) };
+    Ok(())
+}
+
+/// Sends a command to the controller and reads its reply
+pub fn command_with_reply(cmd: u8) -> Result<u8, Error> {
+    command(cmd)?;
+    read()
+}
+
+/// Reads the configuration byte
+pub fn config() -> Result<u8, Error> {
+    command_with_reply(CMD_READ_CONFIG)
+}
+
+/// Writes the configuration byte
+pub fn set_config(config: u8) -> Result<(), Error> {
+    command_with_data(CMD_WRITE_CONFIG, config)
+}
+
+/// Sends a byte to the device on `port`
+pub fn write(port: Port, byte: u8) -> Result<(), Error> {
+    if port == Port::Second {
+        command(CMD_WRITE_SECOND)?;
+    }
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { outb(DATA, byte) };
+    Ok(())
+}
+
+/// Enables or disables the clock of `port`
+pub fn set_enabled(port: Port, enabled: bool) -> Result<(), Error> {
+    command(match (port, enabled) {
+        (Port::First, true) => CMD_ENABLE_FIRST,
+        (Port::First, false) => CMD_DISABLE_FIRST,
+        (Port::Second, true) => CMD_ENABLE_SECOND,
+        (Port::Second, false) => CMD_DISABLE_SECOND,
+    })
+}
+
+fn test_port(port: Port) -> Result<(), Error> {
+    let cmd = match port {
+        Port::First => CMD_TEST_FIRST,
+        Port::Second => CMD_TEST_SECOND,
+    };
+    match command_with_reply(cmd)? {
+        TEST_PORT_OK => Ok(()),
+        code => Err(Error::PortTest(port, code)),
+    }
+}
+
+/// Resets the controller to a known state and enables the working ports.
+/// Must run with interrupts disabled.
+pub fn init() -> Result<Ports, Error> {
+    set_enabled(Port::First, false)?;
+    set_enabled(Port::Second, false)?;
+    flush();
+
+    let mut config = config()? & !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATION);
+    let mut dual = config & CONFIG_SECOND_CLOCK_DISABLED != 0;
+    set_config(config)?;
+
+    match command_with_reply(CMD_TEST_CONTROLLER)? {
+        TEST_CONTROLLER_OK => {}
+        code => return Err(Error::ControllerTest(code)),
+    }
+    // the self-test may reset the controller
+    set_config(config)?;
+
+    // a single port controller ignores enabling the second port
+    if dual {
+        set_enabled(Port::Second, true)?;
+        dual = self::config()? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+        set_enabled(Port::Second, false)?;
+    }
+
+    let mut ports = Ports::default();
+    match test_port(Port::First) {
+        Ok(()) => ports.first = true,
+        Err(e) => log::warn!("PS/2 port 1 failed its self-test: {:?}", e),
+    }
+    if dual {
+        match test_port(Port::Second) {
+            Ok(()) => ports.second = true,
+            Err(e) => log::warn!("PS/2 port 2 failed its self-test: {:?}", e),
+        }
+    }
+
+    if ports.first {
+        set_enabled(Port::First, true)?;
+        config = (config | CONFIG_FIRST_IRQ) & !CONFIG_FIRST_CLOCK_DISABLED;
+    }
+    if ports.second {
+        set_enabled(Port::Second, true)?;
+        config = (config | CONFIG_SECOND_IRQ) & !CONFIG_SECOND_CLOCK_DISABLED;
+    }
+    set_config(config)?;
+    flush();
+    log::info!(
+        "Initialized the PS/2 controller, port 1: {}, port 2: {}",
+        ports.first,
+        ports.second
+    );
+    Ok(ports)
+}
@@ -0,0 +1,543 @@
+//! The PS/2 keyboard on the first controller port.
+//!
+//! Scancodes arriving on IRQ 1 are decoded into `input::KeyEvent`s and pushed
+//! into the input queue. The driver tracks the modifier keys and keeps the
+//! Caps/Num/Scroll Lock LEDs in sync; the LED commands are sent from the
+//! interrupt handler, one byte per acknowledgement, so it never has to poll.
+
+use super::{Error, Port};
+use crate::input::{self, KeyCode, KeyEvent, Modifiers};
+use crate::interrupts::irq;
+
+const CMD_SET_LEDS: u8 = 0xED;
+const CMD_SCANCODE_SET: u8 = 0xF0;
+const CMD_ENABLE_SCANNING: u8 = 0xF4;
+const CMD_DISABLE_SCANNING: u8 = 0xF5;
+const CMD_RESET: u8 = 0xFF;
+
+const REPLY_ACK: u8 = 0xFA;
+const REPLY_RESEND: u8 = 0xFE;
+const REPLY_SELF_TEST_PASSED: u8 = 0xAA;
+/// Sent instead of a scancode on key detection errors or buffer overruns
+const REPLY_ERROR_SET1: u8 = 0xFF;
+const REPLY_ERROR_SET2: u8 = 0x00;
+
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// How often a command is repeated when the keyboard asks for a resend
+const RETRIES: usize = 3;
+
+/// The scancode set the keyboard sends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScancodeSet {
+    /// XT scancodes, either sent by the keyboard or translated by the controller
+    Set1,
+    /// AT scancodes, the default of every PS/2 keyboard
+    Set2,
+}
+
+/// Turns a stream of scancode bytes into key presses and releases
+struct Decoder {
+    set: ScancodeSet,
+    /// an 0xE0 prefix was received
+    extended: bool,
+    /// a set 2 0xF0 release prefix was received
+    released: bool,
+    /// remaining bytes of the pause key sequence, which has no release
+    pause_remaining: u8,
+}
+
+impl Decoder {
+    const fn new(set: ScancodeSet) -> Decoder {
+        Decoder {
+            set,
+            extended: false,
+            released: false,
+            pause_remaining: 0,
+        }
+    }
+
+    /// Returns the key and whether it was pressed, once a scancode is complete
+    fn feed(&mut self, byte: u8) -> Option<(KeyCode, bool)> {
+        if self.pause_remaining > 0 {
+            self.pause_remaining -= 1;
+            return None;
+        }
+        match (self.set, byte) {
+            (_, 0xE0) => {
+                self.extended = true;
+                None
+            }
+            (ScancodeSet::Set1, 0xE1) => {
+                // E1 1D 45 E1 9D C5
+                self.pause_remaining = 5;
+                Some((KeyCode::Pause, true))
+            }
+            (ScancodeSet::Set2, 0xE1) => {
+                // E1 14 77 E1 F0 14 F0 77
+                self.pause_remaining = 7;
+                Some((KeyCode::Pause, true))
+            }
+            (ScancodeSet::Set2, 0xF0) => {
+                self.released = true;
+                None
+            }
+            (ScancodeSet::Set1, _) => {
+                let extended = core::mem::replace(&mut self.extended, false);
+                set1(byte & 0x7F, extended).map(|code| (code, byte & 0x80 == 0))
+            }
+            (ScancodeSet::Set2, _) => {
+                let extended = core::mem::replace(&mut self.extended, false);
+                let released = core::mem::replace(&mut self.released, false);
+                set2(byte, extended).map(|code| (code, !released))
+            }
+        }
+    }
+}
+
+/// Progress of an LED update, which is a command byte and a value byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LedUpdate {
+    Idle,
+    SentCommand,
+    SentValue,
+}
+
+struct Keyboard {
+    decoder: Decoder,
+    modifiers: Modifiers,
+    /// bit `KeyCode as u8` is set while that key is held
+    held: u128,
+    leds: LedUpdate,
+    /// the locks changed while an update was in flight
+    leds_outdated: bool,
+}
+
+impl Keyboard {
+    fn led_value(&self) -> u8 {
+        let mut value = 0;
+        if self.modifiers.scroll_lock() {
+            value |= LED_SCROLL_LOCK;
+        }
+        if self.modifiers.num_lock() {
+            value |= LED_NUM_LOCK;
+        }
+        if self.modifiers.caps_lock() {
+            value |= LED_CAPS_LOCK;
+        }
+        value
+    }
+
+    fn send(&self, byte: u8) {
+        if let Err(e) = super::write(Port::First, byte) {
+            log::warn!("Failed to send 0x{:x} to the keyboard: {:?}", byte, e);
+        }
+    }
+
+    fn update_leds(&mut self) {
+        if self.leds == LedUpdate::Idle {
+            self.send(CMD_SET_LEDS);
+            self.leds = LedUpdate::SentCommand;
+        } else {
+            self.leds_outdated = true;
+        }
+    }
+
+    fn acknowledged(&mut self) {
+        match self.leds {
+            LedUpdate::Idle => {}
+            LedUpdate::SentCommand => {
+                self.send(self.led_value());
+                self.leds = LedUpdate::SentValue;
+            }
+            LedUpdate::SentValue => {
+                self.leds = LedUpdate::Idle;
+                if core::mem::replace(&mut self.leds_outdated, false) {
+                    self.update_leds();
+                }
+            }
+        }
+    }
+
+    fn resend(&mut self) {
+        match self.leds {
+            LedUpdate::Idle => {}
+            LedUpdate::SentCommand => self.send(CMD_SET_LEDS),
+            LedUpdate::SentValue => self.send(self.led_value()),
+        }
+    }
+
+    fn key(&mut self, code: KeyCode, pressed: bool) -> KeyEvent {
+        let bit = 1u128 << code as u8;
+        let repeat = pressed && self.held & bit != 0;
+        if pressed {
+            self.held |= bit;
+        } else {
+            self.held &= !bit;
+        }
+
+        let modifier = match code {
+            KeyCode::LeftShift => Modifiers::LEFT_SHIFT,
+            KeyCode::RightShift => Modifiers::RIGHT_SHIFT,
+            KeyCode::LeftCtrl => Modifiers::LEFT_CTRL,
+            KeyCode::RightCtrl => Modifiers::RIGHT_CTRL,
+            KeyCode::LeftAlt => Modifiers::ALT,
+            KeyCode::RightAlt => Modifiers::ALT_GR,
+            _ => Modifiers::NONE,
+        };
+        self.modifiers.set(modifier, pressed);
+
+        let lock = match code {
+            KeyCode::CapsLock => Modifiers::CAPS_LOCK,
+            KeyCode::NumLock => Modifiers::NUM_LOCK,
+            KeyCode::ScrollLock => Modifiers::SCROLL_LOCK,
+            _ => Modifiers::NONE,
+        };
+        if lock != Modifiers::NONE && pressed && !repeat {
+            self.modifiers.toggle(lock);
+            self.update_leds();
+        }
+
+        KeyEvent {
+            code,
+            pressed,
+            modifiers: self.modifiers,
+        }
+    }
+
+    fn process(&mut self, byte: u8) -> Option<KeyEvent> {
+        match byte {
+            REPLY_ACK => self.acknowledged(),
+            REPLY_RESEND => self.resend(),
+            REPLY_ERROR_SET1 | REPLY_ERROR_SET2 => log::debug!("Keyboard buffer overrun"),
+            _ => {
+                let (code, pressed) = self.decoder.feed(byte)?;
+                return Some(self.key(code, pressed));
+            }
+        }
+        None
+    }
+}
+
+static KEYBOARD: spin::Mutex<Option<Keyboard>> = spin::Mutex::new(None);
+
+/// Sends a command byte and waits for its acknowledgement, polling
+fn command(byte: u8) -> Result<(), Error> {
+    for _ in 0..RETRIES {
+        super::write(Port::First, byte)?;
+        match super::read()? {
+            REPLY_ACK => return Ok(()),
+            REPLY_RESEND => continue,
+            reply => return Err(Error::UnexpectedReply(reply)),
+        }
+    }
+    Err(Error::UnexpectedReply(REPLY_RESEND))
+}
+
+/// Switches to set 2, or falls back to controller translation to set 1
+fn select_scancode_set() -> Result<ScancodeSet, Error> {
+    let set2 = command(CMD_SCANCODE_SET)
+        .and_then(|_| command(2))
+        .and_then(|_| command(CMD_SCANCODE_SET))
+        .and_then(|_| command(0))
+        .and_then(|_| super::read());
+    match set2 {
+        Ok(2) => Ok(ScancodeSet::Set2),
+        Ok(1) => Ok(ScancodeSet::Set1),
+        other => {
+            log::warn!(
+                "The keyboard can't use scancode set 2 ({:x?}), using translation",
+                other
+            );
+            super::set_config(super::config()? | super::CONFIG_TRANSLATION)?;
+            Ok(ScancodeSet::Set1)
+        }
+    }
+}
+
+/// Resets the keyboard on the first port and starts handling its IRQ.
+/// Must run with interrupts disabled, after `ps2::init`.
+pub fn init() -> Result<(), Error> {
+    command(CMD_DISABLE_SCANNING)?;
+    command(CMD_RESET)?;
+    match super::read()? {
+        REPLY_SELF_TEST_PASSED => {}
+        reply => return Err(Error::UnexpectedReply(reply)),
+    }
+    let set = select_scancode_set()?;
+    command(CMD_SET_LEDS)?;
+    command(0)?;
+    command(CMD_ENABLE_SCANNING)?;
+    super::flush();
+
+    *KEYBOARD.lock() = Some(Keyboard {
+        decoder: Decoder::new(set),
+        modifiers: Modifiers::NONE,
+        held: 0,
+        leds: LedUpdate::Idle,
+        leds_outdated: false,
+    });
+    irq::register(irq::KEYBOARD, handle_irq);
+    log::info!("Initialized the PS/2 keyboard using scancode {:?}", set);
+    Ok(())
+}
+
+/// The currently held modifiers and active locks
+pub fn modifiers() -> Modifiers {
+    crate::cpu::without_interrupts(|| {
+        KEYBOARD
+            .lock()
+            .as_ref()
+            .map_or(Modifiers::NONE, |k| k.modifiers)
+    })
+}
+
+fn handle_irq() {
+    // the IRQ can arrive for a byte which was already read while polling
+    if !super::has_data() {
+        return;
+    }
+    let byte = super::read_data();
+    let event = KEYBOARD.lock().as_mut().and_then(|k| k.process(byte));
+    if let Some(event) = event {
+        input::push(event);
+    }
+}
+
+/// Scancode set 1, without the release bit
+fn set1(code: u8, extended: bool) -> Option<KeyCode> {
+    use KeyCode::*;
+    if extended {
+        return Some(match code {
+            0x1C => KeypadEnter,
+            0x1D => RightCtrl,
+            0x35 => KeypadSlash,
+            0x37 => PrintScreen,
+            0x38 => RightAlt,
+            0x47 => Home,
+            0x48 => Up,
+            0x49 => PageUp,
+            0x4B => Left,
+            0x4D => Right,
+            0x4F => End,
+            0x50 => Down,
+            0x51 => PageDown,
+            0x52 => Insert,
+            0x53 => Delete,
+            0x5B => LeftSuper,
+            0x5C => RightSuper,
+            0x5D => Menu,
+            // includes the fake shifts around print screen and the navigation keys
+            _ => return None,
+        });
+    }
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Num1,
+        0x03 => Num2,
+        0x04 => Num3,
+        0x05 => Num4,
+        0x06 => Num5,
+        0x07 => Num6,
+        0x08 => Num7,
+        0x09 => Num8,
+        0x0A => Num9,
+        0x0B => Num0,
+        0x0C => Minus,
+        0x0D => Equals,
+        0x0E => Backspace,
+        0x0F => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1A => LeftBracket,
+        0x1B => RightBracket,
+        0x1C => Enter,
+        0x1D => LeftCtrl,
+        0x1E => A,
+        0x1F => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => Semicolon,
+        0x28 => Quote,
+        0x29 => Backtick,
+        0x2A => LeftShift,
+        0x2B => Backslash,
+        0x2C => Z,
+        0x2D => X,
+        0x2E => C,
+        0x2F => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Period,
+        0x35 => Slash,
+        0x36 => RightShift,
+        0x37 => KeypadStar,
+        0x38 => LeftAlt,
+        0x39 => Space,
+        0x3A => CapsLock,
+        0x3B => F1,
+        0x3C => F2,
+        0x3D => F3,
+        0x3E => F4,
+        0x3F => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumLock,
+        0x46 => ScrollLock,
+        0x47 => Keypad7,
+        0x48 => Keypad8,
+        0x49 => Keypad9,
+        0x4A => KeypadMinus,
+        0x4B => Keypad4,
+        0x4C => Keypad5,
+        0x4D => Keypad6,
+        0x4E => KeypadPlus,
+        0x4F => Keypad1,
+        0x50 => Keypad2,
+        0x51 => Keypad3,
+        0x52 => Keypad0,
+        0x53 => KeypadPeriod,
+        0x56 => Iso102,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None,
+    })
+}
+
+/// Scancode set 2, without prefixes
+fn set2(code: u8, extended: bool) -> Option<KeyCode> {
+    use KeyCode::*;
+    if extended {
+        return Some(match code {
+            0x11 => RightAlt,
+            0x14 => RightCtrl,
+            0x1F => LeftSuper,
+            0x27 => RightSuper,
+            0x2F => Menu,
+            0x4A => KeypadSlash,
+            0x5A => KeypadEnter,
+            0x69 => End,
+            0x6B => Left,
+            0x6C => Home,
+            0x70 => Insert,
+            0x71 => Delete,
+            0x72 => Down,
+            0x74 => Right,
+            0x75 => Up,
+            0x7A => PageDown,
+            0x7C => PrintScreen,
+            0x7D => PageUp,
+            // includes the fake shifts around print screen and the navigation keys
+            _ => return None,
+        });
+    }
+    Some(match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0A => F8,
+        0x0B => F6,
+        0x0C => F4,
+        0x0D => Tab,
+        0x0E => Backtick,
+        0x11 => LeftAlt,
+        0x12 => LeftShift,
+        0x14 => LeftCtrl,
+        0x15 => Q,
+        0x16 => Num1,
+        0x1A => Z,
+        0x1B => S,
+        0x1C => A,
+        0x1D => W,
+        0x1E => Num2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Num4,
+        0x26 => Num3,
+        0x29 => Space,
+        0x2A => V,
+        0x2B => F,
+        0x2C => T,
+        0x2D => R,
+        0x2E => Num5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Num6,
+        0x3A => M,
+        0x3B => J,
+        0x3C => U,
+        0x3D => Num7,
+        0x3E => Num8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Num0,
+        0x46 => Num9,
+        0x49 => Period,
+        0x4A => Slash,
+        0x4B => L,
+        0x4C => Semicolon,
+        0x4D => P,
+        0x4E => Minus,
+        0x52 => Quote,
+        0x54 => LeftBracket,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => RightShift,
+        0x5A => Enter,
+        0x5B => RightBracket,
+        0x5D => Backslash,
+        0x61 => Iso102,
+        0x66 => Backspace,
+        0x69 => Keypad1,
+        0x6B => Keypad4,
+        0x6C => Keypad7,
+        0x70 => Keypad0,
+        0x71 => KeypadPeriod,
+        0x72 => Keypad2,
+        0x73 => Keypad5,
+        0x74 => Keypad6,
+        0x75 => Keypad8,
+        0x76 => Escape,
+        0x77 => NumLock,
+        0x78 => F11,
+        0x79 => KeypadPlus,
+        0x7A => Keypad3,
+        0x7B => KeypadMinus,
+        0x7C => KeypadStar,
+        0x7D => Keypad9,
+        0x7E => ScrollLock,
+        0x83 => F7,
+        _ => return None,
+    })
+}
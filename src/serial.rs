@@ -0,0 +1,444 @@
+//! 16550 UARTs on the four standard COM ports.
+//!
+//! `init` looks for a UART at each port with the scratch register and the
+//! loopback self-test, and configures the ones it finds for 115200 baud 8N1
+//! with their FIFOs enabled. Writes wait for the transmit holding register
+//! to be empty. Received bytes arrive on the port's IRQ and wait in a ring
+//! buffer until they're `read`, or go straight to the port's handler if it
+//! has one, see `set_handler`.
+//!
+//! Writing doesn't take a lock, so the logger can use a port from any
+//! context. Ports `init` didn't get to yet are written to as the firmware
+//! left them, which is enough for QEMU's `-serial stdio`.
+
+use crate::cpu;
+use crate::interrupts::irq;
+use crate::memio::{inb, outb};
+use crate::util::ring::RingBuffer;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+/// Divisor latch low and high byte, while `LCR_DLAB` is set
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+const INTERRUPT_ID: u16 = 2;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+
+const IER_RECEIVED: u8 = 1 << 0;
+
+/// Enable the FIFOs, clear both, interrupt once 14 bytes are received
+const FCR_ENABLE: u8 = 0xC7;
+/// Interrupt identification bits telling that the FIFOs are enabled and work
+const IIR_FIFO: u8 = 0xC0;
+
+/// Divisor latch access bit
+const LCR_DLAB: u8 = 1 << 7;
+const LCR_TWO_STOP_BITS: u8 = 1 << 2;
+
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT1: u8 = 1 << 2;
+/// Connects the UART's interrupt line to the interrupt controller
+const MCR_OUT2: u8 = 1 << 3;
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_OVERRUN: u8 = 1 << 1;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// The UART clock divided by 16, the divisor is taken from this
+const MAX_BAUD: u32 = 115_200;
+
+/// Polls of the line status register before giving up, each takes about a microsecond
+const TIMEOUT: usize = 100_000;
+
+/// Received bytes kept for each port until they're read
+const RECEIVE_SIZE: usize = 256;
+
+/// A COM port
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Com {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl Com {
+    pub const ALL: [Com; 4] = [Com::Com1, Com::Com2, Com::Com3, Com::Com4];
+
+    /// The first I/O port of the UART
+    pub fn base(self) -> u16 {
+        match self {
+            Com::Com1 => 0x3F8,
+            Com::Com2 => 0x2F8,
+            Com::Com3 => 0x3E8,
+            Com::Com4 => 0x2E8,
+        }
+    }
+
+    /// The ISA IRQ line, shared by COM1 and COM3 and by COM2 and COM4
+    pub fn irq(self) -> u8 {
+        match self {
+            Com::Com1 | Com::Com3 => irq::COM1,
+            Com::Com2 | Com::Com4 => irq::COM2,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn read(self, reg: u16) -> u8 {
+        unsafe { inb(self.base() + reg) }
+    }
+
+    fn write(self, reg: u16, val: u8) {
+        unsafe { outb(self.base() + reg, val) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// The parity bit is always 1
+    Mark,
+    /// The parity bit is always 0
+    Space,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    /// Two, or one and a half with 5 data bits
+    Two,
+}
+
+/// The line settings of a port
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// 115200 divided by an integer
+    pub baud: u32,
+    /// 5 to 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Config {
+    /// 115200 baud 8N1
+    pub const DEFAULT: Config = Config {
+        baud: MAX_BAUD,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+    };
+
+    /// The divisor latch and line control register values
+    fn registers(&self) -> Result<(u16, u8), Error> {
+        if self.baud == 0 || MAX_BAUD % self.baud != 0 || MAX_BAUD / self.baud > 0xFFFF {
+            return Err(Error::Baud(self.baud));
+        }
+        if !(5..=8).contains(&self.data_bits) {
+            return Err(Error::DataBits(self.data_bits));
+        }
+        let parity = match self.parity {
+            Parity::None => 0x00,
+            Parity::Odd => 0x08,
+            Parity::Even => 0x18,
+            Parity::Mark => 0x28,
+            Parity::Space => 0x38,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => LCR_TWO_STOP_BITS,
+        };
+        let line_control = (self.data_bits - 5) | stop_bits | parity;
+        Ok(((MAX_BAUD / self.baud) as u16, line_control))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::DEFAULT
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Nothing answers at the port
+    NotPresent,
+    /// The loopback self-test read this byte back instead of the one sent
+    Loopback(u8),
+    /// The baud rate isn't 115200 divided by an integer
+    Baud(u32),
+    /// Data bits other than 5 to 8
+    DataBits(u8),
+    /// The UART didn't become ready in time
+    Timeout,
+}
+
+const STATE_UNKNOWN: u8 = 0;
+const STATE_MISSING: u8 = 1;
+const STATE_READY: u8 = 2;
+/// Like `STATE_READY`, with working FIFOs
+const STATE_READY_FIFO: u8 = 3;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNKNOWN: AtomicU8 = AtomicU8::new(STATE_UNKNOWN);
+static STATES: [AtomicU8; 4] = [UNKNOWN; 4];
+
+/// Bytes received on a port
+struct Receiver {
+    buffer: RingBuffer<u8, RECEIVE_SIZE>,
+    config: Config,
+    /// bytes lost because the buffer or the UART's FIFO was full
+    dropped: usize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_RECEIVER: spin::Mutex<Option<Receiver>> = spin::Mutex::new(None);
+/// Only accessed with interrupts disabled, the IRQ handler fills them
+static RECEIVERS: [spin::Mutex<Option<Receiver>>; 4] = [NO_RECEIVER; 4];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static HANDLERS: [AtomicUsize; 4] = [NO_HANDLER; 4];
+
+fn state(com: Com) -> u8 {
+    STATES[com.index()].load(Ordering::Acquire)
+}
+
+/// Whether `init` found a working UART at `com`
+pub fn is_present(com: Com) -> bool {
+    state(com) >= STATE_READY
+}
+
+/// Whether the UART at `com` has working FIFOs, i.e. is a 16550A or newer
+pub fn has_fifo(com: Com) -> bool {
+    state(com) == STATE_READY_FIFO
+}
+
+/// The ports `init` found a working UART at
+pub fn ports() -> impl Iterator<Item = Com> {
+    Com::ALL.iter().copied().filter(|&com| is_present(com))
+}
+
+/// Looks for a UART at `com` and runs its loopback self-test
+fn probe(com: Com) -> Result<(), Error> {
+    // an empty port reads as 0xFF, a UART without scratch register doesn't have FIFOs either
+    com.write(SCRATCH, 0x5A);
+    if com.read(SCRATCH) != 0x5A || com.read(LINE_STATUS) == 0xFF {
+        return Err(Error::NotPresent);
+    }
+    com.write(INTERRUPT_ENABLE, 0);
+    com.write(MODEM_CONTROL, MCR_LOOPBACK | MCR_RTS | MCR_OUT1 | MCR_OUT2);
+    // drop anything received before
+    while com.read(LINE_STATUS) & LSR_DATA_READY != 0 {
+        com.read(DATA);
+    }
+    com.write(DATA, 0xAE);
+    let result = wait_for(com, LSR_DATA_READY).map(|_| com.read(DATA));
+    com.write(MODEM_CONTROL, 0);
+    match result? {
+        0xAE => Ok(()),
+        other => Err(Error::Loopback(other)),
+    }
+}
+
+fn wait_for(com: Com, mask: u8) -> Result<(), Error> {
+    for _ in 0..TIMEOUT {
+        if com.read(LINE_STATUS) & mask != 0 {
+            return Ok(());
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// Sets the line settings of `com`, enables its FIFOs and its receive interrupt.
+/// Received bytes are dropped.
+pub fn configure(com: Com, config: Config) -> Result<(), Error> {
+    if !is_present(com) {
+        return Err(Error::NotPresent);
+    }
+    let (divisor, line_control) = config.registers()?;
+    cpu::without_interrupts(|| {
+        com.write(INTERRUPT_ENABLE, 0);
+        com.write(LINE_CONTROL, LCR_DLAB);
+        com.write(DIVISOR_LOW, divisor as u8);
+        com.write(DIVISOR_HIGH, (divisor >> 8) as u8);
+        com.write(LINE_CONTROL, line_control);
+        com.write(FIFO_CONTROL, FCR_ENABLE);
+        let fifo = com.read(INTERRUPT_ID) & IIR_FIFO == IIR_FIFO;
+        STATES[com.index()].store(
+            if fifo { STATE_READY_FIFO } else { STATE_READY },
+            Ordering::Release,
+        );
+        com.write(MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT2);
+        *RECEIVERS[com.index()].lock() = Some(Receiver {
+            buffer: RingBuffer::new(),
+            config,
+            dropped: 0,
+        });
+        com.write(INTERRUPT_ENABLE, IER_RECEIVED);
+    });
+    Ok(())
+}
+
+/// The line settings of `com`, `None` if there's no UART
+pub fn config(com: Com) -> Option<Config> {
+    cpu::without_interrupts(|| RECEIVERS[com.index()].lock().as_ref().map(|r| r.config))
+}
+
+/// Probes COM1 to COM4, configures the UARTs found with `Config::DEFAULT`
+/// and handles their IRQs. Must run after `interrupts::init`.
+pub fn init() {
+    for com in Com::ALL.iter().copied() {
+        match probe(com) {
+            Ok(()) => {
+                STATES[com.index()].store(STATE_READY, Ordering::Release);
+                if let Err(e) = configure(com, Config::DEFAULT) {
+                    log::warn!("Failed to configure {:?}: {:?}", com, e);
+                    continue;
+                }
+                log::info!(
+                    "Initialized {:?} at 0x{:x}, {} UART, {} baud",
+                    com,
+                    com.base(),
+                    if has_fifo(com) { "16550A" } else { "8250" },
+                    Config::DEFAULT.baud
+                );
+            }
+            Err(Error::NotPresent) => {
+                STATES[com.index()].store(STATE_MISSING, Ordering::Release);
+            }
+            Err(e) => {
+                STATES[com.index()].store(STATE_MISSING, Ordering::Release);
+                log::warn!("{:?} failed its self-test: {:?}", com, e);
+            }
+        }
+    }
+    if is_present(Com::Com1) || is_present(Com::Com3) {
+        irq::register(irq::COM1, handle_com1_irq);
+    }
+    if is_present(Com::Com2) || is_present(Com::Com4) {
+        irq::register(irq::COM2, handle_com2_irq);
+    }
+}
+
+/// Sends `byte`, waiting for the transmitter to take it.
+/// Bytes for a port `init` found no UART at are dropped.
+pub fn write_byte(com: Com, byte: u8) -> Result<(), Error> {
+    if state(com) == STATE_MISSING {
+        return Err(Error::NotPresent);
+    }
+    wait_for(com, LSR_THR_EMPTY)?;
+    com.write(DATA, byte);
+    Ok(())
+}
+
+/// Sends `bytes`, see `write_byte`
+pub fn write(com: Com, bytes: &[u8]) -> Result<(), Error> {
+    bytes.iter().try_for_each(|&b| write_byte(com, b))
+}
+
+/// Takes the oldest received byte
+pub fn read(com: Com) -> Option<u8> {
+    cpu::without_interrupts(|| RECEIVERS[com.index()].lock().as_mut()?.buffer.pop())
+}
+
+/// Halts until a byte arrives on `com` and takes it. Needs interrupts enabled.
+/// Returns `None` if there's no UART.
+pub fn read_blocking(com: Com) -> Option<u8> {
+    loop {
+        cpu::disable_interrupts();
+        match RECEIVERS[com.index()].lock().as_mut() {
+            None => {
+                cpu::enable_interrupts();
+                return None;
+            }
+            Some(receiver) => {
+                if let Some(byte) = receiver.buffer.pop() {
+                    cpu::enable_interrupts();
+                    return Some(byte);
+                }
+            }
+        }
+        // sti only takes effect after hlt, so a byte can't slip in between
+        cpu::enable_interrupts_and_hlt();
+    }
+}
+
+/// Number of received bytes lost so far because they weren't read in time
+pub fn dropped(com: Com) -> usize {
+    cpu::without_interrupts(|| {
+        RECEIVERS[com.index()]
+            .lock()
+            .as_ref()
+            .map_or(0, |r| r.dropped)
+    })
+}
+
+/// Hands the bytes received on `com` to `handler` instead of buffering them,
+/// `None` buffers them again. The handler runs in the IRQ handler.
+pub fn set_handler(com: Com, handler: Option<fn(u8)>) {
+    HANDLERS[com.index()].store(handler.map_or(0, |f| f as usize), Ordering::Release);
+}
+
+fn handler(com: Com) -> Option<fn(u8)> {
+    match HANDLERS[com.index()].load(Ordering::Acquire) {
+        0 => None,
+        // SAFETY: only `fn(u8)`s are ever stored
+        handler => Some(unsafe { core::mem::transmute::<usize, fn(u8)>(handler) }),
+    }
+}
+
+/// Moves the received bytes of the ports on IRQ `line` into their buffers
+fn receive(line: u8) {
+    for com in Com::ALL.iter().copied().filter(|com| com.irq() == line) {
+        let mut receiver = RECEIVERS[com.index()].lock();
+        let receiver = match receiver.as_mut() {
+            Some(receiver) => receiver,
+            None => continue,
+        };
+        loop {
+            let status = com.read(LINE_STATUS);
+            if status & LSR_OVERRUN != 0 {
+                receiver.dropped += 1;
+            }
+            if status & LSR_DATA_READY == 0 {
+                break;
+            }
+            let byte = com.read(DATA);
+            if let Some(handler) = handler(com) {
+                handler(byte);
+            } else if receiver.buffer.push(byte).is_err() {
+                receiver.dropped += 1;
+            }
+        }
+    }
+}
+
+fn handle_com1_irq() {
+    receive(irq::COM1);
+}
+
+fn handle_com2_irq() {
+    receive(irq::COM2);
+}
+
+/// Writes text to a serial port as it is, for `write!`
+pub struct Writer(pub Com);
+
+impl core::fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write(self.0, s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
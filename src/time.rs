@@ -0,0 +1,36 @@
+//! Clocks and timers.
+//!
+//! The time since boot comes from the TSC, calibrated by `init`.
+
+pub mod pit;
+pub mod tsc;
+
+/// Calibrates the TSC, time starts counting from here
+pub fn init() {
+    let khz = tsc::calibrate();
+    log::info!(
+        "TSC runs at {}.{:03} MHz, {}",
+        khz / 1000,
+        khz % 1000,
+        if crate::cpu::has_invariant_tsc() {
+            "invariant"
+        } else {
+            "not invariant, time may drift"
+        }
+    );
+}
+
+/// Microseconds since boot, 0 before `init`
+pub fn uptime_us() -> u64 {
+    tsc::since_boot()
+}
+
+/// A time since boot in microseconds, shown as seconds like `    1.234567`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Uptime(pub u64);
+
+impl core::fmt::Display for Uptime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:5}.{:06}", self.0 / 1_000_000, self.0 % 1_000_000)
+    }
+}
@@ -0,0 +1,51 @@
+//! The 8253/8254 programmable interval timer.
+//!
+//! Only channel 2 is used, as a precise one-shot delay for calibrating other
+//! clocks. Its gate and output are wired to port 0x61 instead of an IRQ, so
+//! it can be polled with interrupts disabled.
+
+use crate::memio::{inb, outb};
+
+/// The frequency the PIT counters are driven with
+pub const FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Bit 0 drives the channel 2 gate, bit 1 the speaker, bit 5 reads the channel 2 output
+const CONTROL_PORT_B: u16 = 0x61;
+
+/// The longest delay `wait` supports, in microseconds
+pub const MAX_WAIT_US: u32 = (0xFFFF_u64 * 1_000_000 / FREQUENCY as u64) as u32;
+
+/// Busy waits `us` microseconds (at most `MAX_WAIT_US`) using channel 2.
+/// Panics on longer waits.
+pub fn wait(us: u32) {
+    if us > MAX_WAIT_US {
+        panic!("pit::wait({}): longer than {}us", us, MAX_WAIT_US);
+    }
+    let count = (us as u64 * FREQUENCY as u64 / 1_000_000).max(1) as u16;
+    start(count);
+    while !expired() {
+        core::hint::spin_loop();
+    }
+}
+
+/// Starts channel 2 counting down from `count` in mode 0, poll `expired` for the end
+pub fn start(count: u16) {
+    unsafe {
+        // gate low while programming, speaker off
+        let port_b = inb(CONTROL_PORT_B) & !0b11;
+        outb(CONTROL_PORT_B, port_b);
+        // channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count), binary
+        outb(COMMAND, 0b1011_0000);
+        outb(CHANNEL_2, count as u8);
+        outb(CHANNEL_2, (count >> 8) as u8);
+        // raising the gate starts the countdown
+        outb(CONTROL_PORT_B, port_b | 1);
+    }
+}
+
+/// Whether the countdown started by `start` has reached zero
+pub fn expired() -> bool {
+    unsafe { inb(CONTROL_PORT_B) & 0x20 != 0 }
+}
@@ -0,0 +1,63 @@
+//! The time stamp counter as a clock.
+//!
+//! `calibrate` measures the rate of the TSC against PIT channel 2. The TSC
+//! counts at that rate in every power state only if it's invariant, which
+//! `cpu::has_invariant_tsc` tells. Older CPUs slow it down along with the
+//! core, their clock runs late while the CPU is throttled.
+
+use super::pit;
+use crate::cpu;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// PIT ticks each calibration run waits, 10ms
+const CALIBRATION_TICKS: u16 = (pit::FREQUENCY / 100) as u16;
+/// Calibration runs, the shortest one counts
+const CALIBRATION_RUNS: usize = 3;
+
+/// TSC cycles per millisecond, 0 until calibrated
+static KHZ: AtomicU64 = AtomicU64::new(0);
+/// The TSC when `calibrate` was first called
+static BOOT: AtomicU64 = AtomicU64::new(0);
+
+/// Measures the rate of the TSC, returning it in kHz.
+/// The first call also sets the time 0 of `since_boot`.
+pub fn calibrate() -> u64 {
+    let _ = BOOT.compare_exchange(0, cpu::rdtsc(), Ordering::AcqRel, Ordering::Acquire);
+    let cycles = cpu::without_interrupts(|| {
+        (0..CALIBRATION_RUNS)
+            .map(|_| {
+                pit::start(CALIBRATION_TICKS);
+                let start = cpu::rdtsc();
+                while !pit::expired() {
+                    core::hint::spin_loop();
+                }
+                cpu::rdtsc() - start
+            })
+            .min()
+            .unwrap_or(0)
+    });
+    let khz = cycles * pit::FREQUENCY as u64 / CALIBRATION_TICKS as u64 / 1000;
+    KHZ.store(khz, Ordering::Release);
+    khz
+}
+
+/// The rate of the TSC in kHz, 0 before `calibrate`
+pub fn khz() -> u64 {
+    KHZ.load(Ordering::Acquire)
+}
+
+/// Converts TSC cycles to microseconds, 0 before `calibrate`
+pub fn to_micros(cycles: u64) -> u64 {
+    match khz() {
+        0 => 0,
+        khz => (cycles as u128 * 1000 / khz as u128) as u64,
+    }
+}
+
+/// Microseconds since `calibrate` was first called, 0 before
+pub fn since_boot() -> u64 {
+    match BOOT.load(Ordering::Acquire) {
+        0 => 0,
+        boot => to_micros(cpu::rdtsc().saturating_sub(boot)),
+    }
+}
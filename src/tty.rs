@@ -1,9 +1,29 @@
+pub mod ansi;
+pub mod cp437;
+pub mod display;
+pub mod framebuffer;
+pub mod mirror;
+pub mod scrollback;
 pub mod vgatext;
 
+pub use vgatext::palette::{Palette, Rgb};
 pub use vgatext::Character;
 pub use vgatext::Color;
+pub use vgatext::CursorShape;
+pub use vgatext::Mode;
 pub use vgatext::TextColor;
 
+use crate::input::{KeyCode, KeyEvent};
+use crate::multiboot::{BootInfo, FramebufferKind};
+use crate::serial::Com;
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use mirror::Mirror;
+use scrollback::Scrollback;
+
+/// Lines of history a TTY keeps by default
+pub const HISTORY_DEPTH: usize = 1000;
+
 /// The TTY is the interface the kernel uses to interact with a text screen. [^newline]
 /// If you want a terminal like interface, use the `kprint!` or `kprintln!`
 /// macros for formatted input and automated flushing[^macros].
@@ -11,8 +31,9 @@ pub use vgatext::TextColor;
 /// You can retreive the position of the *next* character to be written character via `continue_pos`
 /// (you may also set it via `set_pos`).
 ///
-/// [^newline]: Newlines don't work. The full character map is called CP437, the
-///     code 10 (\n) is displayed as ◙
+/// [^newline]: The `append_*` functions understand `\n`, `\r`, `\t`, `\b`, the bell and
+///     ANSI escape sequences, see the `ansi` module. The other functions draw every
+///     byte as its CP437 glyph, e.g. code 10 (\n) is displayed as ◙
 ///
 /// [^macros]: DO NOT use these while holding the TTY lock, you *will* deadlock.
 /// ```rust
@@ -75,9 +96,33 @@ pub struct TTY {
     /// tracks position for the ktty* macros
     pos: usize,
     col: TextColor,
-    buff: [Character; 2000],
-    /// if the tty is a copy, any accesses to vram are ignored
+    width: usize,
+    height: usize,
+    /// `width * height` characters, row by row
+    buff: Vec<Character>,
+    /// if the tty is a copy or a console in the background, any accesses to vram are ignored
     is_copy: bool,
+    /// a character was written to the last column, the next one goes on a new line
+    wrap_pending: bool,
+    /// position and color saved by `ESC 7` or `CSI s`
+    saved: (usize, TextColor),
+    /// first and last row which scroll on a line feed, set by `CSI top;bottom r`
+    region: (usize, usize),
+    /// the colors set through SGR sequences, `col` is derived from them
+    attributes: ansi::Attributes,
+    parser: ansi::Parser,
+    cursor_visible: bool,
+    cursor_shape: CursorShape,
+    /// the colors `Color`s are drawn with while the TTY is on screen
+    palette: Palette,
+    /// lines which scrolled off the top
+    history: Scrollback,
+    /// how many lines the view is scrolled back into the history, 0 shows `buff`
+    view: usize,
+    /// cells changed since the last flush, `start..end`, empty if `start >= end`
+    dirty: (usize, usize),
+    /// the serial port the output is copied to
+    mirror: Option<Mirror>,
 }
 
 impl TTY {
@@ -85,11 +130,46 @@ impl TTY {
     /// It's used to `tty.sync(tty_copy)` with the actually tty
     #[allow(clippy::new_without_default)]
     pub fn new() -> TTY {
+        TTY::with_vram(false)
+    }
+
+    /// Creates a TTY the size of the screen
+    fn with_vram(vram: bool) -> TTY {
+        let (width, height) = display::dimensions();
         TTY {
             pos: 0,
             col: TextColor::default(),
-            buff: [Character::blank(); 2000],
-            is_copy: true,
+            width,
+            height,
+            buff: vec![Character::blank(); width * height],
+            is_copy: !vram,
+            wrap_pending: false,
+            saved: (0, TextColor::default()),
+            region: (0, height - 1),
+            attributes: ansi::Attributes::from_color(TextColor::default()),
+            parser: ansi::Parser::new(),
+            cursor_visible: true,
+            cursor_shape: CursorShape::Underline,
+            palette: Palette::VGA,
+            history: Scrollback::new(width, HISTORY_DEPTH),
+            view: 0,
+            dirty: (0, width * height),
+            mirror: None,
+        }
+    }
+
+    /// Whether the TTY is the one shown on the screen
+    pub fn is_on_screen(&self) -> bool {
+        !self.is_copy
+    }
+
+    /// Brings the TTY to the screen and repaints it, or sends it to the background
+    fn set_on_screen(&mut self, on_screen: bool) {
+        self.is_copy = !on_screen;
+        if on_screen {
+            display::set_cursor_shape(self.cursor_shape);
+            display::set_palette(&self.palette);
+            self.flush_all();
         }
     }
 
@@ -99,7 +179,41 @@ impl TTY {
     /// let (width, height) = tty.dimensions();
     /// ```
     pub fn dimensions(&self) -> (usize, usize) {
-        (vgatext::WIDTH, vgatext::HEIGHT)
+        (self.width, self.height)
+    }
+
+    /// Changes the dimensions of the TTY, keeping the text around the cursor.
+    /// If the cursor's line doesn't fit anymore, lines are moved into the history.
+    /// Lines are cut or padded to the new width. Does not flush the screen.
+    pub fn resize(&mut self, width: usize, height: usize) -> &mut Self {
+        if width == 0 || height == 0 {
+            panic!("TTY::resize({}, {}): empty TTY", width, height);
+        }
+        let (x, y) = (self.pos % self.width, self.pos / self.width);
+        let shift = (y + 1).saturating_sub(height);
+        let mut buff = vec![Character::blank(); width * height];
+        let columns = width.min(self.width);
+        for row in 0..self.height.min(height + shift) {
+            let line = &self.buff[row * self.width..(row + 1) * self.width];
+            if row < shift {
+                self.history.push(line);
+            } else {
+                let start = (row - shift) * width;
+                buff[start..start + columns].copy_from_slice(&line[..columns]);
+            }
+        }
+        self.history.set_width(width);
+        self.width = width;
+        self.height = height;
+        self.buff = buff;
+        self.pos = x.min(width - 1) + (y - shift) * width;
+        self.wrap_pending = false;
+        self.saved = (0, self.saved.1);
+        self.region = (0, height - 1);
+        self.view = 0;
+        // the old range may reach past the smaller buffer
+        self.dirty = (0, width * height);
+        self
     }
 
     /// The default color used for printing characters
@@ -111,6 +225,47 @@ impl TTY {
     /// This color's background color will be used for clearing as well
     pub fn set_color(&mut self, col: TextColor) -> &mut Self {
         self.col = col;
+        self.attributes = ansi::Attributes::from_color(col);
+        self
+    }
+
+    /// Whether the hardware cursor is shown at `continue_pos` after a flush
+    pub fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    /// Shows or hides the hardware cursor, takes effect on the next flush
+    pub fn set_cursor_visible(&mut self, visible: bool) -> &mut Self {
+        self.cursor_visible = visible;
+        self
+    }
+
+    /// The shape of the hardware cursor
+    pub fn cursor_shape(&self) -> CursorShape {
+        self.cursor_shape
+    }
+
+    /// Changes the shape of the hardware cursor
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) -> &mut Self {
+        self.cursor_shape = shape;
+        if !self.is_copy {
+            display::set_cursor_shape(shape);
+        }
+        self
+    }
+
+    /// The colors `Color`s are drawn with
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Changes the colors `Color`s are drawn with, like `Palette::SOLARIZED`.
+    /// Takes effect immediately if the TTY is on screen, otherwise once it's switched to.
+    pub fn set_palette(&mut self, palette: &Palette) -> &mut Self {
+        self.palette = *palette;
+        if !self.is_copy {
+            display::set_palette(palette);
+        }
         self
     }
 
@@ -121,22 +276,23 @@ impl TTY {
 
     /// Overwrites the k* tty macro position, panics if pos is invalid
     pub fn set_pos(&mut self, pos: (usize, usize)) -> &mut Self {
-        if pos.0 >= vgatext::WIDTH || pos.1 >= vgatext::HEIGHT {
+        if pos.0 >= self.width || pos.1 >= self.height {
             panic!("set_pos(({},{})): invalid position", pos.0, pos.1);
         }
-        self.pos = pos.0 + pos.1 * vgatext::WIDTH;
+        self.pos = pos.0 + pos.1 * self.width;
+        self.wrap_pending = false;
         self
     }
 
     /// A convenience to write multile colored characters at once.
     /// Works with newlines.
     pub fn cputstr(&mut self, pos: (usize, usize), str: &[Character]) -> &mut Self {
-        if pos.0 >= vgatext::WIDTH || pos.1 >= vgatext::HEIGHT {
+        if pos.0 >= self.width || pos.1 >= self.height {
             panic!(
                 "putstr(({},{}), {{string}}): invalid position",
                 pos.0, pos.1
             );
-        } else if pos.0 + pos.1 * vgatext::WIDTH + str.len() >= vgatext::WIDTH * vgatext::HEIGHT {
+        } else if pos.0 + pos.1 * self.width + str.len() >= self.width * self.height {
             panic!(
                 "putstr(({},{}), {{len: {}}}): string too big",
                 pos.0,
@@ -145,9 +301,9 @@ impl TTY {
             );
         }
         let mut i = 0;
-        let start = pos.0 + pos.1 * vgatext::WIDTH;
+        let start = pos.0 + pos.1 * self.width;
         for &b in str.iter() {
-            if start + i == vgatext::WIDTH * vgatext::HEIGHT {
+            if start + i == self.width * self.height {
                 panic!(
                     "putstr(({},{}), {{len: {}}}): string too big",
                     pos.0,
@@ -155,24 +311,25 @@ impl TTY {
                     str.len()
                 );
             } else if b.ascii() == b'\n' {
-                i += 80 - (pos.0 + i) % 80;
+                i += self.width - (pos.0 + i) % self.width;
             } else {
                 self.buff[start + i] = b;
             }
             i += 1;
         }
+        self.touch(start, start + i);
         self
     }
 
     /// A conveniece to write multiple ascii characters at once.
     /// Works with newlines.
     pub fn putstr(&mut self, pos: (usize, usize), str: &[u8]) -> &mut Self {
-        if pos.0 >= vgatext::WIDTH || pos.1 >= vgatext::HEIGHT {
+        if pos.0 >= self.width || pos.1 >= self.height {
             panic!(
                 "putstr(({},{}), {{string}}): invalid position",
                 pos.0, pos.1
             );
-        } else if pos.0 + pos.1 * vgatext::WIDTH + str.len() >= vgatext::WIDTH * vgatext::HEIGHT {
+        } else if pos.0 + pos.1 * self.width + str.len() >= self.width * self.height {
             panic!(
                 "putstr(({},{}), {{len: {}}}): string too big",
                 pos.0,
@@ -181,9 +338,9 @@ impl TTY {
             );
         }
         let mut i = 0;
-        let start = pos.0 + pos.1 * vgatext::WIDTH;
+        let start = pos.0 + pos.1 * self.width;
         for &b in str.iter() {
-            if start + i == vgatext::WIDTH * vgatext::HEIGHT {
+            if start + i == self.width * self.height {
                 panic!(
                     "putstr(({},{}), {{len: {}}}): string too big",
                     pos.0,
@@ -192,52 +349,57 @@ impl TTY {
                 );
             }
             if b == b'\n' {
-                i += 80 - (pos.0 + i) % 80;
+                i += self.width - (pos.0 + i) % self.width;
             } else {
                 self.buff[start + i] = Character::new(b, self.col);
             }
             i += 1;
         }
+        self.touch(start, start + i);
         self
     }
 
     /// Writes a colored character to the screen. Flushes the (single) character
     pub fn cput(&mut self, pos: (usize, usize), c: Character) -> &mut Self {
-        if pos.0 >= vgatext::WIDTH || pos.1 >= vgatext::HEIGHT {
+        if pos.0 >= self.width || pos.1 >= self.height {
             panic!(
                 "cput(({},{}), {{character}}): invalid position",
                 pos.0, pos.1
             )
         }
-        self.buff[pos.0 + pos.1 * vgatext::WIDTH] = c;
+        let index = pos.0 + pos.1 * self.width;
+        self.buff[index] = c;
+        self.touch(index, index + 1);
         self
     }
 
     /// Writes an ascii character to the screen. Flushes the (single) character
     pub fn put(&mut self, pos: (usize, usize), c: u8) -> &mut Self {
-        if pos.0 >= vgatext::WIDTH || pos.1 >= vgatext::HEIGHT {
+        if pos.0 >= self.width || pos.1 >= self.height {
             panic!(
                 "put(({},{}), {{character}}): invalid position",
                 pos.0, pos.1
             )
         }
         let c = Character::new(c, self.color());
-        self.buff[pos.0 + pos.1 * vgatext::WIDTH] = c;
+        let index = pos.0 + pos.1 * self.width;
+        self.buff[index] = c;
+        self.touch(index, index + 1);
         self
     }
 
     /// Writes a colored character to the screen. Also writes directly to video memory.
     /// Use this instead of immediately flushing, as it is much cheaper
     pub fn cput_force(&mut self, pos: (usize, usize), c: Character) -> &mut Self {
-        if pos.0 >= vgatext::WIDTH || pos.1 >= vgatext::HEIGHT {
+        if pos.0 >= self.width || pos.1 >= self.height {
             panic!(
                 "cput_force(({},{}), {{character}}): invalid position",
                 pos.0, pos.1
             )
         }
-        self.buff[pos.0 + pos.1 * vgatext::WIDTH] = c;
+        self.buff[pos.0 + pos.1 * self.width] = c;
         if !self.is_copy {
-            unsafe { vgatext::writechar(pos, c) }
+            unsafe { display::writechar(pos, c) }
         }
         self
     }
@@ -245,62 +407,77 @@ impl TTY {
     /// Writes an ascii character to the screen. Also writes directly to video memory.
     /// Use this instead of flushing, as it is much cheaper
     pub fn put_force(&mut self, pos: (usize, usize), c: u8) -> &mut Self {
-        if pos.0 >= vgatext::WIDTH || pos.1 >= vgatext::HEIGHT {
+        if pos.0 >= self.width || pos.1 >= self.height {
             panic!(
                 "put_force(({},{}), {{character}}): invalid position",
                 pos.0, pos.1
             )
         }
         let c = Character::new(c, self.color());
-        self.buff[pos.0 + pos.1 * vgatext::WIDTH] = c;
+        self.buff[pos.0 + pos.1 * self.width] = c;
         if !self.is_copy {
-            unsafe { vgatext::writechar(pos, c) }
+            unsafe { display::writechar(pos, c) }
         }
         self
     }
 
     /// Get the buffered character, NOT necessarily the currently displayed one.
     pub fn get(&mut self, pos: (usize, usize)) -> Character {
-        self.buff[pos.0 + pos.1 * vgatext::WIDTH]
+        self.buff[pos.0 + pos.1 * self.width]
     }
 
     /// Append-Writes a colored Character.
     /// This is mainly used by the k* tty macros.
-    /// If the TTY is full, it acts as a FIFO, discarding beginning characters.
+    /// Control characters and escape sequences are carried out instead of drawn.
+    /// `\n` moves to the start of the next line, scrolling at the bottom.
     pub fn append_char(&mut self, c: Character) -> &mut Self {
-        if c.ascii() == b'\n' {
-            if self.pos / vgatext::WIDTH == vgatext::HEIGHT - 1 {
-                self.buff.rotate_left(vgatext::WIDTH);
-                self.buff[(vgatext::HEIGHT - 1) * vgatext::WIDTH..].fill(Character::blank());
-                self.pos = (vgatext::HEIGHT - 1) * vgatext::WIDTH;
-            } else {
-                self.pos = (self.pos / vgatext::WIDTH + 1) * vgatext::WIDTH;
+        if let Some(action) = self.parser.feed(c.ascii()) {
+            if let Some(mirror) = &mut self.mirror {
+                mirror.action(&action, self.col);
             }
-        } else if self.pos == self.buff.len() {
-            self.buff.rotate_left(1);
-            self.buff[self.buff.len() - 1] = c;
+            self.perform(action, c.color());
+        }
+        self
+    }
+
+    /// Draws `c` at the cursor and advances it, wrapping at the end of the line
+    fn put_glyph(&mut self, c: Character) {
+        if self.wrap_pending {
+            self.wrap_pending = false;
+            self.carriage_return();
+            self.line_feed();
+        }
+        self.buff[self.pos] = c;
+        self.touch(self.pos, self.pos + 1);
+        if let Some(mirror) = &mut self.mirror {
+            mirror.glyph(c);
+        }
+        if self.pos % self.width == self.width - 1 {
+            self.wrap_pending = true;
         } else {
-            self.buff[self.pos] = c;
             self.pos += 1;
         }
-        self
     }
 
-    /// Append-Writes an ascii string
-    /// If the TTY is full, it acts as a FIFO, discarding beginning characters.
+    /// Append-Writes an UTF-8 string, translated to CP437.
+    /// Characters without a CP437 glyph and invalid UTF-8 are drawn as `cp437::replacement()`.
+    /// If the TTY is full, it scrolls, discarding the top line.
     pub fn append_str(&mut self, c: &[u8]) -> &mut Self {
-        // optimization for bigger than TTY strings
-        // TODO: Optimize more. Rotate up to max(remaining, 80)s chars at newline
-        let count =
-            (c.len() / (vgatext::WIDTH * vgatext::HEIGHT)) * vgatext::HEIGHT * vgatext::WIDTH;
-        if count == 0 {
-            for &b in c {
-                self.append_char(Character::new(b, self.col));
-            }
-        } else {
-            for &b in &c[c.len() - (count * vgatext::WIDTH * vgatext::HEIGHT)..] {
-                self.append_char(Character::new(b, self.col));
+        crate::util::utf8::decode(c, |ch| match ch {
+            Some(ch) if ch.is_ascii() => {
+                self.append_char(Character::new(ch as u8, self.col));
             }
+            // non-ASCII glyphs in the control range, like '◙', are drawn
+            _ => self.put_glyph(Character::new(cp437::encode(ch), self.col)),
+        });
+        self
+    }
+
+    /// Append-Writes CP437 bytes as they are, control characters are drawn as glyphs
+    /// If the TTY is full, it scrolls, discarding the top line.
+    pub fn append_cp437(&mut self, c: &[u8]) -> &mut Self {
+        for &b in c {
+            self.put_glyph(Character::new(b, self.col));
         }
         self
     }
@@ -312,8 +489,10 @@ impl TTY {
     /// ```
     pub fn clear_char(&mut self, clear_char: Character) -> &mut Self {
         self.buff.fill(clear_char);
+        self.touch_all();
         self.flush();
         self.pos = 0;
+        self.wrap_pending = false;
         self
     }
 
@@ -326,27 +505,181 @@ impl TTY {
     /// Note: this does include clearing the screen
     pub fn reset(&mut self) -> &mut Self {
         self.pos = 0;
-        self.col = TextColor::default();
+        self.set_color(TextColor::default());
         self.buff.fill(Character::blank());
+        self.wrap_pending = false;
+        self.saved = (0, TextColor::default());
+        self.region = (0, self.height - 1);
+        self.parser.reset();
+        self.cursor_visible = true;
+        self.set_cursor_shape(CursorShape::Underline);
+        self.set_palette(&Palette::VGA);
+        // also marks the whole screen for the next flush
+        self.clear_history();
         if !self.is_copy {
-            vgatext::reset();
+            display::reset();
         }
+        if let Some(mirror) = &mut self.mirror {
+            mirror.reset();
+        }
+        self
+    }
+
+    /// The serial port the output is copied to
+    pub fn mirror(&self) -> Option<Com> {
+        self.mirror.as_ref().map(Mirror::com)
+    }
+
+    /// Copies everything appended from now on to the serial port `com`,
+    /// colors included, see the `mirror` module. `None` stops it.
+    pub fn set_mirror(&mut self, com: Option<Com>) -> &mut Self {
+        self.mirror = com.map(Mirror::new);
         self
     }
 
-    /// Writes the character buffer to the actual video memory
+    /// Marks the cells `start..end` as changed, to be written by the next flush.
+    /// `end` may lie past the buffer, e.g. after a `\n` on the last line of `putstr`.
+    pub(super) fn touch(&mut self, start: usize, end: usize) {
+        let end = end.min(self.buff.len());
+        let (dirty_start, dirty_end) = self.dirty;
+        if dirty_start >= dirty_end {
+            self.dirty = (start, end);
+        } else {
+            self.dirty = (dirty_start.min(start), dirty_end.max(end));
+        }
+    }
+
+    fn touch_all(&mut self) {
+        self.touch(0, self.buff.len());
+    }
+
+    /// Writes the characters changed since the last flush to the actual video memory
+    /// and moves the hardware cursor to `continue_pos`.
+    /// While scrolled back, the history is shown instead and the cursor is hidden.
     pub fn flush(&mut self) -> &mut Self {
         if !self.is_copy {
-            unsafe {
-                vgatext::write_at((0, 0), &self.buff);
+            if self.view == 0 {
+                let (start, end) = self.dirty;
+                if start < end {
+                    let pos = (start % self.width, start / self.width);
+                    unsafe { display::write_at(pos, &self.buff[start..end]) };
+                }
+                self.dirty = (0, 0);
+            } else {
+                for y in 0..self.height {
+                    unsafe { display::write_at((0, y), self.view_line(y)) };
+                }
             }
+            self.sync_cursor();
+        }
+        self
+    }
+
+    /// Writes the whole character buffer to the actual video memory, see `flush`
+    pub fn flush_all(&mut self) -> &mut Self {
+        self.touch_all();
+        self.flush()
+    }
+
+    fn sync_cursor(&self) {
+        let visible = self.cursor_visible && self.view == 0;
+        if visible {
+            display::set_cursor_pos((self.pos % self.width, self.pos / self.width));
+        }
+        display::set_cursor_enabled(visible);
+    }
+
+    /// Line `y` of the screen while scrolled back
+    fn view_line(&self, y: usize) -> &[Character] {
+        let history = self.history.len();
+        if y < self.view {
+            self.history.line(history - self.view + y)
+        } else {
+            let y = y - self.view;
+            &self.buff[y * self.width..(y + 1) * self.width]
         }
+    }
+
+    /// Number of lines in the history
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Changes how many lines of history are kept, 0 disables it
+    pub fn set_history_depth(&mut self, lines: usize) -> &mut Self {
+        self.history.set_depth(lines);
+        self.view = self.view.min(self.history.len());
+        self.touch_all();
         self
     }
 
-    /// Copies `other`s buffer. Does not flush the screen.
+    /// Forgets the history
+    pub fn clear_history(&mut self) -> &mut Self {
+        self.history.clear();
+        self.view = 0;
+        self.touch_all();
+        self
+    }
+
+    /// How many lines the view is scrolled back, 0 when the current screen is shown
+    pub fn scroll_position(&self) -> usize {
+        self.view
+    }
+
+    /// Scrolls the view `lines` further back into the history. Flushes the screen.
+    pub fn scroll_up(&mut self, lines: usize) -> &mut Self {
+        self.view = (self.view + lines).min(self.history.len());
+        self.flush_all()
+    }
+
+    /// Scrolls the view `lines` towards the current screen. Flushes the screen.
+    pub fn scroll_down(&mut self, lines: usize) -> &mut Self {
+        self.view = self.view.saturating_sub(lines);
+        self.flush_all()
+    }
+
+    /// Scrolls back by half a screen
+    pub fn page_up(&mut self) -> &mut Self {
+        self.scroll_up(self.height / 2)
+    }
+
+    /// Scrolls forward by half a screen
+    pub fn page_down(&mut self) -> &mut Self {
+        self.scroll_down(self.height / 2)
+    }
+
+    /// Shows the current screen again
+    pub fn scroll_to_bottom(&mut self) -> &mut Self {
+        if self.view != 0 {
+            self.view = 0;
+            self.flush_all();
+        }
+        self
+    }
+
+    /// Writes the history and the screen as UTF-8 text, without trailing blanks
+    pub fn write_history<W: core::fmt::Write>(&self, w: &mut W) -> core::fmt::Result {
+        let screen = self.buff.chunks(self.width);
+        for line in self.history.lines().chain(screen) {
+            let end = line
+                .iter()
+                .rposition(|c| c.ascii() != b' ' && c.ascii() != 0)
+                .map_or(0, |i| i + 1);
+            for c in &line[..end] {
+                w.write_char(cp437::to_char(c.ascii()))?;
+            }
+            w.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    /// Copies `other`s buffer, taking over its dimensions. Does not flush the screen.
     pub fn sync(&mut self, other: &TTY) -> &mut Self {
+        if self.dimensions() != other.dimensions() {
+            self.resize(other.width, other.height);
+        }
         self.buff.copy_from_slice(&other.buff);
+        self.touch_all();
         self
     }
 
@@ -360,6 +693,7 @@ impl TTY {
             );
         }
         self.buff.copy_from_slice(other);
+        self.touch_all();
         self
     }
 
@@ -371,30 +705,276 @@ impl TTY {
     /// Copies itself. New TTY Instance DOES NOT sync with Video RAM
     pub fn copy(&self) -> TTY {
         let mut tty = TTY::new();
-        tty.buff.copy_from_slice(&self.buff);
+        tty.sync(self);
         tty
     }
 }
 
+/// Number of virtual consoles
+pub const CONSOLES: usize = 6;
+/// The console the kernel log is written to
+pub const LOG_CONSOLE: usize = CONSOLES - 1;
+
+fn console_instance(on_screen: bool) -> spin::Mutex<TTY> {
+    spin::Mutex::new(TTY::with_vram(on_screen))
+}
+
 lazy_static::lazy_static!(
-    /// Thread safe, static handle to the TTY
-    static ref TTY_INSTANCE: spin::Mutex<TTY> = spin::Mutex::<TTY>::from((|| {
-         TTY {
-            pos: 0,
-            col: TextColor::default(),
-            buff: [Character::blank();2000],
-            is_copy: false
-        }
-    })());
+    /// Thread safe, static handles to the virtual consoles, the first one starts on screen
+    static ref CONSOLE_INSTANCES: [spin::Mutex<TTY>; CONSOLES] = [
+        console_instance(true),
+        console_instance(false),
+        console_instance(false),
+        console_instance(false),
+        console_instance(false),
+        console_instance(false),
+    ];
 );
 
-/// Initializes the TTY
-pub fn init() {
-    vgatext::vga_init();
+/// Index of the console on screen
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+/// Set once the consoles may be used by the logger
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Initializes the TTY, on the framebuffer console if the bootloader set up a graphics mode
+pub fn init(boot_info: &BootInfo) {
+    match boot_info.framebuffer() {
+        Some(info) if !matches!(info.kind, FramebufferKind::EgaText) => {
+            if let Err(e) = framebuffer::init(&info) {
+                log::warn!("Failed to initialize the framebuffer console: {:?}", e);
+            }
+        }
+        _ => {}
+    }
+    if !framebuffer::is_active() {
+        vgatext::vga_init();
+    }
+    #[cfg(feature = "embedded-font")]
+    let embedded = match vgatext::font::Font::from_psf(vgatext::font::EMBEDDED) {
+        Ok(font) => Some(font),
+        Err(e) => {
+            log::warn!("Failed to load the embedded font: {:?}", e);
+            None
+        }
+    };
+    #[cfg(not(feature = "embedded-font"))]
+    let embedded: Option<vgatext::font::Font> = None;
+    // the first font loaded fixes the character size of the framebuffer console
+    cp437::set_czech(true);
+    match embedded {
+        Some(font) => vgatext::font::load(&font),
+        None => vgatext::font::load_czech(),
+    }
+    READY.store(true, Ordering::Release);
 }
 
+/// The kernel's console, the first virtual console.
+/// It's the one `kprint!` writes to, whether it's on screen or not.
 pub fn tty() -> &'static spin::Mutex<TTY> {
-    &*TTY_INSTANCE
+    console(0)
+}
+
+/// The virtual console `index`, panics if there is no such console
+pub fn console(index: usize) -> &'static spin::Mutex<TTY> {
+    if index >= CONSOLES {
+        panic!("console({}): there are only {} consoles", index, CONSOLES);
+    }
+    &CONSOLE_INSTANCES[index]
+}
+
+/// Index of the console on screen
+pub fn active_index() -> usize {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// The console on screen, keyboard input belongs to it
+pub fn active() -> &'static spin::Mutex<TTY> {
+    console(active_index())
+}
+
+/// Puts the console `index` on screen, repainting it. Panics if there is no such console.
+/// DO NOT call while holding a console lock.
+pub fn switch_to(index: usize) {
+    let next = console(index);
+    let previous = ACTIVE.swap(index, Ordering::AcqRel);
+    if previous == index {
+        return;
+    }
+    console(previous).lock().set_on_screen(false);
+    next.lock().set_on_screen(true);
+    log::debug!("Switched to console {}", index);
+}
+
+/// Switches the VGA to `mode` and resizes every console to it.
+/// Text modes aren't available on the framebuffer console.
+/// DO NOT call while holding a console lock.
+pub fn set_mode(mode: Mode) {
+    if framebuffer::is_active() {
+        log::warn!("Can't switch to {:?}, the TTY is on the framebuffer", mode);
+        return;
+    }
+    let (width, height) = mode.dimensions();
+    let active = active_index();
+    for (index, console) in CONSOLE_INSTANCES.iter().enumerate() {
+        let mut tty = console.lock();
+        tty.resize(width, height);
+        if index == active {
+            vgatext::set_mode(mode);
+            tty.set_on_screen(true);
+        }
+    }
+}
+
+/// The console of the kernel log, `None` before `init` or while the console is in use.
+/// Meant for the logger, which mustn't deadlock if it interrupts a writer.
+pub fn log_console() -> Option<spin::MutexGuard<'static, TTY>> {
+    if READY.load(Ordering::Acquire) {
+        console(LOG_CONSOLE).try_lock()
+    } else {
+        None
+    }
+}
+
+/// Makes the kernel's console usable over the serial port `com`: its output is
+/// mirrored there and characters arriving on `com` are typed into it, whichever
+/// console is on screen.
+/// DO NOT call while holding a console lock.
+pub fn attach_serial(com: Com) {
+    tty().lock().set_mirror(Some(com));
+    crate::serial::set_handler(com, Some(receive_serial));
+    log::info!("Attached the kernel's console to {:?}", com);
+}
+
+/// Queues a byte from the terminal on the serial port as typed input
+fn receive_serial(byte: u8) {
+    // only called from the serial IRQ handler
+    static DECODER: spin::Mutex<crate::util::utf8::Decoder> =
+        spin::Mutex::new(crate::util::utf8::Decoder::new());
+    DECODER.lock().push(byte, |ch| {
+        let byte = match ch {
+            // terminals send a carriage return for enter and DEL for backspace
+            Some('\r') => b'\n',
+            Some('\x7F') => 0x08,
+            ch => cp437::encode(ch),
+        };
+        crate::input::push_char(byte);
+    });
+}
+
+/// Writes the history and screen of the console on screen to the serial port
+pub fn dump_history() {
+    let _ = active()
+        .lock()
+        .write_history(&mut crate::serial::Writer(crate::serial::Com::Com1));
+}
+
+/// Handles the TTY's own key bindings. Alt+F1 to Alt+F6 switch the consoles,
+/// Shift+PgUp/PgDn scroll through the history, Alt+F12 switches to the next
+/// keyboard layout.
+/// Returns whether the key was consumed. Other keys don't scroll back to the
+/// current screen, as modifiers are keys too, the character typed does that.
+pub fn handle_key(event: &KeyEvent) -> bool {
+    if !event.pressed {
+        return false;
+    }
+    let function_keys = [
+        KeyCode::F1,
+        KeyCode::F2,
+        KeyCode::F3,
+        KeyCode::F4,
+        KeyCode::F5,
+        KeyCode::F6,
+    ];
+    if event.modifiers.alt() {
+        if let Some(index) = function_keys.iter().position(|&key| key == event.code) {
+            switch_to(index);
+            return true;
+        }
+        if event.code == KeyCode::F12 {
+            let layout = crate::input::keymap::layout();
+            crate::input::keymap::set_layout(layout.next());
+            return true;
+        }
+    }
+    match event.code {
+        KeyCode::PageUp if event.modifiers.shift() => {
+            active().lock().page_up();
+            true
+        }
+        KeyCode::PageDown if event.modifiers.shift() => {
+            active().lock().page_down();
+            true
+        }
+        _ => false,
+    }
+}
+
+static BELL: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the function called for the bell character, `None` silences it
+pub fn set_bell(bell: Option<fn()>) {
+    BELL.store(bell.map_or(0, |f| f as usize), Ordering::Release);
+}
+
+fn ring_bell() {
+    match BELL.load(Ordering::Acquire) {
+        0 => {}
+        bell => {
+            // SAFETY: only `fn()`s are ever stored
+            let bell: fn() = unsafe { core::mem::transmute(bell) };
+            bell();
+        }
+    }
+}
+
+/// Prints `lines` lines on the kernel's console, once flushing all of VRAM after
+/// every line and once only the changed characters, and logs the cycles each took.
+/// Both are measured twice: rewriting the rows of the screen in place, where only
+/// the line written changes, and appending lines, which scroll the whole screen.
+#[cfg(feature = "tty-bench")]
+pub fn benchmark(lines: usize) {
+    let run = |full: bool, scroll: bool| {
+        let start = crate::cpu::rdtsc();
+        for i in 0..lines {
+            let mut tty = tty().lock();
+            if !scroll {
+                let (_, height) = tty.dimensions();
+                tty.set_pos((0, i % height));
+            }
+            let _ = crate::util::text::format_apply(
+                |s| {
+                    tty.append_str(s.as_bytes());
+                    Ok(())
+                },
+                format_args!(
+                    "benchmark line {} of {}{}",
+                    i,
+                    lines,
+                    if scroll { "\n" } else { "" }
+                ),
+            );
+            if full {
+                tty.flush_all();
+            } else {
+                tty.flush();
+            }
+        }
+        crate::cpu::rdtsc() - start
+    };
+    let in_place = (run(true, false), run(false, false));
+    let scrolling = (run(true, true), run(false, true));
+    log::info!(
+        "TTY benchmark, {} lines rewritten in place: full flush {} cycles, dirty flush {} cycles",
+        lines,
+        in_place.0,
+        in_place.1
+    );
+    log::info!(
+        "TTY benchmark, {} lines appended, scrolling the screen: full flush {} cycles, dirty flush {} cycles",
+        lines,
+        scrolling.0,
+        scrolling.1
+    );
 }
 
 /// Mimics the `print!` macro, but acts on the TTY
@@ -403,11 +983,7 @@ macro_rules! kprint {
     ($($arg:tt)*) => {{
         let mut tty = $crate::tty::tty().lock();
         $crate::util::text::format_apply(|s| {
-            if s.is_ascii() {
-                tty.append_str(s.as_bytes());
-            } else {
-                panic!("kprint!(...): formatted string contains non-ascii characters");
-            }
+            tty.append_str(s.as_bytes());
             Ok(())
         }, format_args!($($arg)*)).unwrap();
         tty.flush();
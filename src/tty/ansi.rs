@@ -0,0 +1,632 @@
+//! A parser for ECMA-48 (ANSI, VT100) escape sequences.
+//!
+//! It follows the structure of the DEC VT parser: bytes are fed one at a time
+//! and complete sequences come out as `Action`s, which the TTY carries out.
+//! Operating system commands and other strings are skipped.
+
+use super::vgatext::palette::{Palette, Rgb};
+use super::vgatext::{Character, Color, CursorShape, TextColor};
+use super::TTY;
+
+/// Parameters kept per control sequence, further ones are dropped
+pub const MAX_PARAMS: usize = 16;
+
+const ESC: u8 = 0x1B;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1A;
+const BEL: u8 = 0x07;
+const DEL: u8 = 0x7F;
+
+/// A control sequence, `ESC [ private? params intermediate? final`
+#[derive(Clone, Copy, Debug)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// a `?`, `<`, `=` or `>` right after the `[`
+    pub private: Option<u8>,
+    pub intermediate: Option<u8>,
+    pub final_byte: u8,
+}
+
+impl Csi {
+    const fn new() -> Csi {
+        Csi {
+            params: [0; MAX_PARAMS],
+            len: 0,
+            private: None,
+            intermediate: None,
+            final_byte: 0,
+        }
+    }
+
+    /// The parameters, omitted ones are 0
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Parameter `index`, or `default` if it's omitted or 0
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&p) if p != 0 => p,
+            _ => default,
+        }
+    }
+}
+
+/// Something the terminal has to do
+#[derive(Clone, Copy, Debug)]
+pub enum Action {
+    /// Draw the byte
+    Print(u8),
+    /// Carry out a C0 control character like `\n`
+    Control(u8),
+    /// An escape sequence, `ESC intermediate? final`
+    Escape {
+        intermediate: Option<u8>,
+        final_byte: u8,
+    },
+    /// A control sequence
+    Csi(Csi),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    /// a malformed control sequence, skipped up to its final byte
+    CsiIgnore,
+    /// an OSC, DCS, SOS, PM or APC string, skipped up to ST or BEL
+    String,
+    /// an ESC inside a string, which may start the ST
+    StringEscape,
+}
+
+pub struct Parser {
+    state: State,
+    csi: Csi,
+    intermediate: Option<u8>,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            csi: Csi::new(),
+            intermediate: None,
+        }
+    }
+
+    /// Whether no sequence is in progress
+    pub fn is_ground(&self) -> bool {
+        self.state == State::Ground
+    }
+
+    /// Abandons a sequence in progress
+    pub fn reset(&mut self) {
+        self.state = State::Ground;
+    }
+
+    fn enter_csi(&mut self) {
+        self.csi = Csi::new();
+        self.state = State::CsiEntry;
+    }
+
+    fn param_digit(&mut self, digit: u8) {
+        if self.csi.len == 0 {
+            self.csi.len = 1;
+        }
+        if let Some(p) = self.csi.params.get_mut(self.csi.len - 1) {
+            *p = p.saturating_mul(10).saturating_add((digit - b'0') as u16);
+        }
+    }
+
+    fn param_separator(&mut self) {
+        if self.csi.len == 0 {
+            // the omitted first parameter
+            self.csi.len = 1;
+        }
+        if self.csi.len < MAX_PARAMS {
+            self.csi.len += 1;
+        }
+    }
+
+    /// Feeds `byte`, returning what to do once something is complete
+    pub fn feed(&mut self, byte: u8) -> Option<Action> {
+        // these work in every state
+        match byte {
+            CAN | SUB => {
+                self.state = State::Ground;
+                return None;
+            }
+            ESC if self.state != State::String => {
+                self.intermediate = None;
+                self.state = State::Escape;
+                return None;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => match byte {
+                0x00..=0x1F => Some(Action::Control(byte)),
+                DEL => None,
+                _ => Some(Action::Print(byte)),
+            },
+            State::Escape | State::EscapeIntermediate => match byte {
+                0x00..=0x1F => Some(Action::Control(byte)),
+                0x20..=0x2F => {
+                    self.intermediate = Some(byte);
+                    self.state = State::EscapeIntermediate;
+                    None
+                }
+                b'[' if self.state == State::Escape => {
+                    self.enter_csi();
+                    None
+                }
+                b']' | b'P' | b'X' | b'^' | b'_' if self.state == State::Escape => {
+                    self.state = State::String;
+                    None
+                }
+                0x30..=0x7E => {
+                    self.state = State::Ground;
+                    Some(Action::Escape {
+                        intermediate: self.intermediate,
+                        final_byte: byte,
+                    })
+                }
+                _ => {
+                    self.state = State::Ground;
+                    None
+                }
+            },
+            State::CsiEntry | State::CsiParam | State::CsiIntermediate | State::CsiIgnore => {
+                match byte {
+                    0x00..=0x1F => Some(Action::Control(byte)),
+                    DEL => None,
+                    b'<'..=b'?' if self.state == State::CsiEntry => {
+                        self.csi.private = Some(byte);
+                        self.state = State::CsiParam;
+                        None
+                    }
+                    b'0'..=b'9' | b';'
+                        if self.state == State::CsiEntry || self.state == State::CsiParam =>
+                    {
+                        if byte == b';' {
+                            self.param_separator();
+                        } else {
+                            self.param_digit(byte);
+                        }
+                        self.state = State::CsiParam;
+                        None
+                    }
+                    0x20..=0x2F if self.state != State::CsiIgnore => {
+                        if self.csi.intermediate.is_some() {
+                            self.state = State::CsiIgnore;
+                        } else {
+                            self.csi.intermediate = Some(byte);
+                            self.state = State::CsiIntermediate;
+                        }
+                        None
+                    }
+                    0x40..=0x7E => {
+                        let ignored = self.state == State::CsiIgnore;
+                        self.state = State::Ground;
+                        if ignored {
+                            None
+                        } else {
+                            self.csi.final_byte = byte;
+                            Some(Action::Csi(self.csi))
+                        }
+                    }
+                    // parameters after an intermediate, ':' sub-parameters, or a late private marker
+                    _ => {
+                        self.state = State::CsiIgnore;
+                        None
+                    }
+                }
+            }
+            State::String => {
+                match byte {
+                    BEL => self.state = State::Ground,
+                    ESC => self.state = State::StringEscape,
+                    _ => {}
+                }
+                None
+            }
+            State::StringEscape => {
+                // ESC \ is the string terminator, any other ESC starts a new sequence
+                if byte == b'\\' {
+                    self.state = State::Ground;
+                    None
+                } else {
+                    self.state = State::Escape;
+                    self.feed(byte)
+                }
+            }
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Colors set through SGR sequences
+#[derive(Clone, Copy, Debug)]
+pub struct Attributes {
+    fore: Color,
+    back: Color,
+    /// drawn with the bright variant of `fore`
+    bold: bool,
+    /// foreground and background swapped
+    reverse: bool,
+}
+
+impl Attributes {
+    pub fn from_color(color: TextColor) -> Attributes {
+        Attributes {
+            fore: color.fore(),
+            back: color.back(),
+            bold: false,
+            reverse: false,
+        }
+    }
+
+    pub fn color(&self) -> TextColor {
+        let fore = if self.bold {
+            Color::from(self.fore as u8 | 8)
+        } else {
+            self.fore
+        };
+        if self.reverse {
+            TextColor::new(self.back, fore)
+        } else {
+            TextColor::new(fore, self.back)
+        }
+    }
+}
+
+/// The color of a `38;5;n`/`48;5;n` or `38;2;r;g;b`/`48;2;r;g;b` SGR parameter
+/// starting at `params[0]`, and the number of parameters it takes up.
+/// Colors beyond the first 16 become the one closest to them in `palette`.
+fn extended_color(params: &[u16], palette: &Palette) -> (Option<Color>, usize) {
+    let rgb_to_color = |r: u16, g: u16, b: u16| {
+        let channel = |v: u16| v.min(255) as u8;
+        palette.nearest(Rgb::new(channel(r), channel(g), channel(b)))
+    };
+    match params {
+        [5, n, ..] => {
+            let color = match *n {
+                0..=15 => Some(Color::from_ansi(*n as u8)),
+                // the 6x6x6 cube and the gray ramp, reduced to 16 colors
+                16..=231 => {
+                    let n = n - 16;
+                    let (r, g, b) = (n / 36, n / 6 % 6, n % 6);
+                    Some(rgb_to_color(r * 51, g * 51, b * 51))
+                }
+                232..=255 => Some(rgb_to_color(
+                    (n - 232) * 10 + 8,
+                    (n - 232) * 10 + 8,
+                    (n - 232) * 10 + 8,
+                )),
+                _ => None,
+            };
+            (color, 2)
+        }
+        [2, r, g, b, ..] => (Some(rgb_to_color(*r, *g, *b)), 4),
+        _ => (None, params.len()),
+    }
+}
+
+impl TTY {
+    /// Carries out `action`, printed characters get `color`
+    pub(super) fn perform(&mut self, action: Action, color: TextColor) {
+        match action {
+            Action::Print(byte) => self.put_glyph(Character::new(byte, color)),
+            Action::Control(byte) => self.control(byte),
+            Action::Escape {
+                intermediate: None,
+                final_byte,
+            } => self.escape(final_byte),
+            Action::Escape { .. } => {}
+            Action::Csi(csi) => match (csi.private, csi.intermediate) {
+                (None, None) => self.csi(&csi),
+                (Some(b'?'), None) => self.private_mode(&csi),
+                // set cursor style
+                (None, Some(b' ')) if csi.final_byte == b'q' => {
+                    self.set_cursor_shape(match csi.param(0, 0) {
+                        0..=2 => CursorShape::Block,
+                        3 | 4 => CursorShape::Underline,
+                        _ => CursorShape::HalfBlock,
+                    });
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        (self.pos % self.width, self.pos / self.width)
+    }
+
+    fn move_to(&mut self, x: usize, y: usize) {
+        self.pos = x.min(self.width - 1) + y.min(self.height - 1) * self.width;
+        self.wrap_pending = false;
+    }
+
+    pub(super) fn carriage_return(&mut self) {
+        let (_, y) = self.cursor();
+        self.move_to(0, y);
+    }
+
+    /// Moves down a line, scrolling if the cursor is on the last line of the scroll region
+    pub(super) fn line_feed(&mut self) {
+        let (x, y) = self.cursor();
+        if y == self.region.1 {
+            self.scroll_region_up(1);
+            self.move_to(x, y);
+        } else {
+            self.move_to(x, y + 1);
+        }
+    }
+
+    /// Moves up a line, scrolling if the cursor is on the first line of the scroll region
+    fn reverse_line_feed(&mut self) {
+        let (x, y) = self.cursor();
+        if y == self.region.0 {
+            self.scroll_region_down(1);
+            self.move_to(x, y);
+        } else {
+            self.move_to(x, y.saturating_sub(1));
+        }
+    }
+
+    fn blank(&self) -> Character {
+        Character::new(b' ', self.col)
+    }
+
+    /// Scrolls the lines of the scroll region up by `n`.
+    /// Lines leaving the top of the screen go into the history.
+    fn scroll_region_up(&mut self, n: usize) {
+        let (top, bottom) = self.region;
+        if top == 0 {
+            for y in 0..n.min(bottom + 1) {
+                self.history
+                    .push(&self.buff[y * self.width..(y + 1) * self.width]);
+            }
+        }
+        self.scroll_lines(top, bottom, n, true);
+    }
+
+    /// Scrolls the lines of the scroll region down by `n`
+    fn scroll_region_down(&mut self, n: usize) {
+        self.scroll_lines(self.region.0, self.region.1, n, false);
+    }
+
+    /// Moves lines `top..=bottom` by `n` lines, blanking the lines scrolled in
+    fn scroll_lines(&mut self, top: usize, bottom: usize, n: usize, up: bool) {
+        let blank = self.blank();
+        let lines = &mut self.buff[top * self.width..(bottom + 1) * self.width];
+        let n = n.min(bottom + 1 - top) * self.width;
+        if up {
+            lines.rotate_left(n);
+            let len = lines.len();
+            lines[len - n..].fill(blank);
+        } else {
+            lines.rotate_right(n);
+            lines[..n].fill(blank);
+        }
+        self.touch(top * self.width, (bottom + 1) * self.width);
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            // bell
+            0x07 => super::ring_bell(),
+            // backspace
+            0x08 => {
+                let (x, y) = self.cursor();
+                self.move_to(x.saturating_sub(1), y);
+            }
+            b'\t' => {
+                let (x, y) = self.cursor();
+                self.move_to((x / 8 + 1) * 8, y);
+            }
+            // line feed, vertical tab and form feed, with an implied carriage return
+            b'\n' | 0x0B | 0x0C => {
+                self.carriage_return();
+                self.line_feed();
+            }
+            b'\r' => self.carriage_return(),
+            _ => {}
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = (self.pos, self.col);
+    }
+
+    fn restore_cursor(&mut self) {
+        let (pos, col) = self.saved;
+        self.set_color(col);
+        self.move_to(pos % self.width, pos / self.width);
+    }
+
+    fn escape(&mut self, final_byte: u8) {
+        match final_byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            // index
+            b'D' => self.line_feed(),
+            // next line
+            b'E' => {
+                self.carriage_return();
+                self.line_feed();
+            }
+            // reverse index
+            b'M' => self.reverse_line_feed(),
+            // full reset
+            b'c' => {
+                self.reset();
+            }
+            _ => {}
+        }
+    }
+
+    /// Blanks the cells `start..end`
+    fn erase(&mut self, start: usize, end: usize) {
+        let blank = self.blank();
+        self.buff[start..end].fill(blank);
+        self.touch(start, end);
+    }
+
+    fn sgr(&mut self, params: &[u16]) {
+        let mut attributes = self.attributes;
+        let mut i = 0;
+        // no parameters mean reset
+        let reset = [0];
+        let params = if params.is_empty() {
+            &reset[..]
+        } else {
+            params
+        };
+        while i < params.len() {
+            match params[i] {
+                0 => attributes = Attributes::from_color(TextColor::default()),
+                1 => attributes.bold = true,
+                22 => attributes.bold = false,
+                7 => attributes.reverse = true,
+                27 => attributes.reverse = false,
+                p @ 30..=37 => attributes.fore = Color::from_ansi((p - 30) as u8),
+                39 => attributes.fore = TextColor::default().fore(),
+                p @ 40..=47 => attributes.back = Color::from_ansi((p - 40) as u8),
+                49 => attributes.back = TextColor::default().back(),
+                p @ 90..=97 => attributes.fore = Color::from_ansi((p - 90 + 8) as u8),
+                p @ 100..=107 => attributes.back = Color::from_ansi((p - 100 + 8) as u8),
+                p @ 38 | p @ 48 => {
+                    let (color, len) = extended_color(&params[i + 1..], &self.palette);
+                    if let Some(color) = color {
+                        if p == 38 {
+                            attributes.fore = color;
+                        } else {
+                            attributes.back = color;
+                        }
+                    }
+                    i += len;
+                }
+                // underline, blink and the like can't be shown
+                _ => {}
+            }
+            i += 1;
+        }
+        self.attributes = attributes;
+        self.col = attributes.color();
+    }
+
+    /// DEC private modes, `CSI ? n h` sets and `CSI ? n l` resets them
+    fn private_mode(&mut self, csi: &Csi) {
+        let set = match csi.final_byte {
+            b'h' => true,
+            b'l' => false,
+            _ => return,
+        };
+        for &mode in csi.params() {
+            // text cursor enable
+            if mode == 25 {
+                self.set_cursor_visible(set);
+            }
+        }
+    }
+
+    fn csi(&mut self, csi: &Csi) {
+        let (x, y) = self.cursor();
+        let n = csi.param(0, 1) as usize;
+        // vertical movement stops at the scroll region if it starts inside
+        let (top, bottom) = self.region;
+        let up_limit = if y >= top { top } else { 0 };
+        let down_limit = if y <= bottom { bottom } else { self.height - 1 };
+        match csi.final_byte {
+            // cursor up, down, forward, back
+            b'A' => self.move_to(x, y.saturating_sub(n).max(up_limit)),
+            b'B' => self.move_to(x, (y + n).min(down_limit)),
+            b'C' => self.move_to(x + n, y),
+            b'D' => self.move_to(x.saturating_sub(n), y),
+            // next line, previous line
+            b'E' => self.move_to(0, (y + n).min(down_limit)),
+            b'F' => self.move_to(0, y.saturating_sub(n).max(up_limit)),
+            // column absolute, line absolute
+            b'G' => self.move_to(n - 1, y),
+            b'd' => self.move_to(x, n - 1),
+            // cursor position, 1 based
+            b'H' | b'f' => {
+                let row = csi.param(0, 1) as usize;
+                let column = csi.param(1, 1) as usize;
+                self.move_to(column - 1, row - 1);
+            }
+            // erase in display
+            b'J' => match csi.param(0, 0) {
+                0 => self.erase(self.pos, self.width * self.height),
+                1 => self.erase(0, self.pos + 1),
+                2 => self.erase(0, self.width * self.height),
+                3 => {
+                    self.erase(0, self.width * self.height);
+                    self.clear_history();
+                }
+                _ => {}
+            },
+            // erase in line
+            b'K' => match csi.param(0, 0) {
+                0 => self.erase(self.pos, (y + 1) * self.width),
+                1 => self.erase(y * self.width, self.pos + 1),
+                2 => self.erase(y * self.width, (y + 1) * self.width),
+                _ => {}
+            },
+            // erase characters
+            b'X' => self.erase(self.pos, (self.pos + n).min((y + 1) * self.width)),
+            // insert and delete characters, shifting the rest of the line
+            b'@' | b'P' => {
+                let blank = self.blank();
+                let line = &mut self.buff[self.pos..(y + 1) * self.width];
+                let n = n.min(line.len());
+                if csi.final_byte == b'@' {
+                    line.rotate_right(n);
+                    line[..n].fill(blank);
+                } else {
+                    line.rotate_left(n);
+                    let len = line.len();
+                    line[len - n..].fill(blank);
+                }
+                self.touch(self.pos, (y + 1) * self.width);
+            }
+            // insert and delete lines, inside the scroll region
+            b'L' | b'M' => {
+                if (top..=bottom).contains(&y) {
+                    self.scroll_lines(y, bottom, n, csi.final_byte == b'M');
+                    self.move_to(0, y);
+                }
+            }
+            // scroll up, scroll down
+            b'S' => self.scroll_region_up(n),
+            b'T' => self.scroll_region_down(n),
+            b'm' => self.sgr(csi.params()),
+            // set the scroll region, which also homes the cursor
+            b'r' => {
+                let top = csi.param(0, 1) as usize;
+                let bottom = (csi.param(1, self.height as u16) as usize).min(self.height);
+                if top < bottom {
+                    self.region = (top - 1, bottom - 1);
+                    self.move_to(0, 0);
+                }
+            }
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+}
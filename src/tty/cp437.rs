@@ -0,0 +1,143 @@
+//! Code page 437, the character set of the VGA text mode font.
+//!
+//! Bytes below 0x20 and 0x7F are drawn as symbols by the VGA, `TABLE` lists
+//! those glyphs. `from_char` maps them back from their usual control
+//! character meaning as well as from the symbol, so both `'\n'` and `'◙'`
+//! give 0x0A. Text is stored with `encode`, which draws characters without a
+//! glyph as the replacement glyph, a `■` unless changed with `set_replacement`.
+//!
+//! With `set_czech`, the Czech and Slovak letters listed in `CZECH` replace
+//! rarely used glyphs, once `vgatext::font` has drawn them.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// The Unicode character drawn for every byte
+#[rustfmt::skip]
+pub const TABLE: [char; 256] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// The Czech and Slovak letters CP437 lacks, in place of the mixed single and double
+/// line box drawings and a few other rarely used glyphs.
+/// Uppercase letters with an acute, "Á" or "Ý", aren't among them.
+#[rustfmt::skip]
+pub const CZECH: [(u8, char); 23] = [
+    (0xB5, 'č'), (0xB6, 'ď'), (0xB7, 'ě'), (0xB8, 'ĺ'), (0xBD, 'ľ'), (0xBE, 'ň'),
+    (0xC6, 'ŕ'), (0xC7, 'ř'), (0xCF, 'š'), (0xD0, 'ť'), (0xD1, 'ů'), (0xD2, 'ý'),
+    (0xD3, 'ž'), (0xD4, 'Č'), (0xD5, 'Ď'), (0xD6, 'Ě'), (0xD7, 'Ľ'), (0xD8, 'Ň'),
+    (0x9E, 'Ř'), (0x9F, 'Š'), (0xA6, 'Ť'), (0xA7, 'Ů'), (0xA9, 'Ž'),
+];
+
+static CZECH_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the letters of `CZECH` are used
+pub fn czech() -> bool {
+    CZECH_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Uses the letters of `CZECH` instead of the glyphs they replace.
+/// `vgatext::font::load_czech` draws them and enables this.
+pub fn set_czech(enabled: bool) {
+    CZECH_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// The character drawn for `byte`
+pub fn to_char(byte: u8) -> char {
+    if czech() {
+        if let Some(&(_, letter)) = CZECH.iter().find(|&&(b, _)| b == byte) {
+            return letter;
+        }
+    }
+    TABLE[byte as usize]
+}
+
+/// The byte whose glyph is `c`, if CP437 has one
+pub fn from_char(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+    if let Some(byte) = (0..=255).find(|&byte| to_char(byte) == c) {
+        return Some(byte);
+    }
+    // characters which look the same as a glyph
+    Some(match c {
+        'β' => 0xE1,
+        'μ' => 0xE6,
+        'Ø' | 'ø' | '∅' | 'ϕ' => 0xED,
+        '∈' | '€' => 0xEE,
+        '⋅' => 0xFA,
+        '¦' => 0x7C,
+        _ => return None,
+    })
+}
+
+/// The letter without its diacritic, for the Czech and Slovak letters CP437
+/// has no glyph for: "ř" becomes "r", "Ů" becomes "U"
+pub fn base_letter(c: char) -> Option<char> {
+    let lower = c.to_lowercase().next()?;
+    let base = match lower {
+        'á' | 'ä' => 'a',
+        'č' | 'ć' => 'c',
+        'ď' | 'đ' => 'd',
+        'é' | 'ě' | 'ë' => 'e',
+        'í' => 'i',
+        'ĺ' | 'ľ' | 'ł' => 'l',
+        'ň' | 'ń' => 'n',
+        'ó' | 'ô' | 'ö' | 'ő' => 'o',
+        'ŕ' | 'ř' => 'r',
+        'š' | 'ś' => 's',
+        'ť' => 't',
+        'ú' | 'ů' | 'ü' | 'ű' => 'u',
+        'ý' => 'y',
+        'ž' | 'ź' | 'ż' => 'z',
+        _ => return None,
+    };
+    if c == lower {
+        Some(base)
+    } else {
+        Some(base.to_ascii_uppercase())
+    }
+}
+
+/// Like `from_char`, but falls back to `base_letter` for letters without a glyph
+pub fn from_char_lossy(c: char) -> Option<u8> {
+    from_char(c).or_else(|| base_letter(c).and_then(from_char))
+}
+
+static REPLACEMENT: AtomicU8 = AtomicU8::new(0xFE);
+
+/// The glyph drawn for characters CP437 lacks
+pub fn replacement() -> u8 {
+    REPLACEMENT.load(Ordering::Relaxed)
+}
+
+/// Changes the glyph drawn for characters CP437 lacks
+pub fn set_replacement(byte: u8) {
+    REPLACEMENT.store(byte, Ordering::Relaxed);
+}
+
+/// The byte for `c`, or the replacement glyph. `None` stands for an invalid
+/// UTF-8 sequence and gives the replacement glyph as well.
+pub fn encode(c: Option<char>) -> u8 {
+    c.and_then(from_char).unwrap_or_else(replacement)
+}
+
+/// The bytes for `s`, see `encode`
+pub fn encode_str(s: &str) -> impl Iterator<Item = u8> + '_ {
+    s.chars().map(|c| encode(Some(c)))
+}
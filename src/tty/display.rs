@@ -0,0 +1,116 @@
+//! The screen the TTY draws on: the VGA text buffer, or the framebuffer console if
+//! the bootloader set up a graphics mode. Every function goes to whichever is active.
+
+use super::vgatext::palette::{self, Palette};
+use super::vgatext::{Character, CursorShape, TextColor};
+use super::{cp437, framebuffer, vgatext};
+
+/// Columns and rows of characters
+pub fn dimensions() -> (usize, usize) {
+    if framebuffer::is_active() {
+        framebuffer::dimensions()
+    } else {
+        vgatext::dimensions()
+    }
+}
+
+/// Write a slice of characters, starting from a specific character.
+/// # Safety
+/// Validate that the position is valid, and that the characters fit
+pub unsafe fn write_at(pos: (usize, usize), src: &[Character]) {
+    if framebuffer::is_active() {
+        framebuffer::write_at(pos, src);
+    } else {
+        vgatext::write_at(pos, src);
+    }
+}
+
+/// Write a single character to a position.
+/// # Safety
+/// Validate that the position is valid
+pub unsafe fn writechar(pos: (usize, usize), c: Character) {
+    if framebuffer::is_active() {
+        framebuffer::writechar(pos, c);
+    } else {
+        vgatext::writechar(pos, c);
+    }
+}
+
+/// Moves the cursor to a position, panics if it's invalid
+pub fn set_cursor_pos(pos: (usize, usize)) {
+    if framebuffer::is_active() {
+        framebuffer::set_cursor_pos(pos);
+    } else {
+        vgatext::set_cursor_pos(pos);
+    }
+}
+
+/// Shows or hides the cursor
+pub fn set_cursor_enabled(enabled: bool) {
+    if framebuffer::is_active() {
+        framebuffer::set_cursor_enabled(enabled);
+    } else {
+        vgatext::set_cursor_enabled(enabled);
+    }
+}
+
+/// Sets the scanlines the cursor covers
+pub fn set_cursor_shape(shape: CursorShape) {
+    if framebuffer::is_active() {
+        framebuffer::set_cursor_shape(shape);
+    } else {
+        vgatext::set_cursor_shape(shape);
+    }
+}
+
+/// Shows the colors of `palette`
+pub fn set_palette(palette: &Palette) {
+    if framebuffer::is_active() {
+        framebuffer::set_palette(palette);
+    } else {
+        palette::set_palette(palette);
+    }
+}
+
+/// Clears the screen
+pub fn reset() {
+    if framebuffer::is_active() {
+        framebuffer::reset();
+    } else {
+        vgatext::reset();
+    }
+}
+
+/// Formats `args` straight onto the screen, bypassing the TTY and its lock.
+/// Starts at cell `pos` and returns the cell after the output.
+/// `\n` moves to the next line, anything past the end of the screen is dropped.
+/// Characters are translated to CP437 like the TTY does.
+/// Meant for the panic and fault handlers, where the TTY may be locked or broken.
+/// The framebuffer console is skipped while it's locked, see `framebuffer::force_unlock`.
+pub fn write_fmt_direct(pos: usize, color: TextColor, args: core::fmt::Arguments<'_>) -> usize {
+    let (width, height) = dimensions();
+    let mut i = pos;
+    let _ = crate::util::text::format_apply(
+        |s| {
+            for ch in cp437::encode_str(s) {
+                if i >= width * height {
+                    break;
+                } else if ch == b'\n' {
+                    i = (i / width + 1) * width;
+                } else {
+                    let pos = (i % width, i / width);
+                    let c = Character::new(ch, color);
+                    if framebuffer::is_active() {
+                        framebuffer::try_write_at(pos, &[c]);
+                    } else {
+                        unsafe { vgatext::writechar(pos, c) };
+                    }
+                    i += 1;
+                }
+            }
+            Ok(())
+        },
+        args,
+    );
+    i
+}
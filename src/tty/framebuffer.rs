@@ -0,0 +1,418 @@
+//! A text console on the linear framebuffer the bootloader set up with a graphics mode.
+//!
+//! It offers what `vgatext` does, cells of `Character`s, a cursor and a palette,
+//! so the TTY can use either through `display`. Glyphs come from `vgatext::font`,
+//! the first font loaded fixes the character height. Every pixel of a glyph is
+//! drawn as a square of `scale` pixels, the largest which still fits 80x25
+//! characters on the screen. The cells on screen are kept to skip writes which
+//! change nothing and to redraw the screen when the font or the palette changes.
+
+use super::vgatext::font::{Glyphs, MAX_HEIGHT};
+use super::vgatext::palette::{Palette, Rgb};
+use super::vgatext::{Character, CursorShape};
+use crate::memory::paging::{self, Flags};
+use crate::multiboot::{self, ColorField, FramebufferKind};
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Why the framebuffer can't be used for the console
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Not a direct color framebuffer, e.g. an indexed one or EGA text
+    Kind,
+    /// Pixels of another size than 16, 24 or 32 bits
+    Bpp(u8),
+    /// Smaller than a single character
+    TooSmall,
+    /// The framebuffer couldn't be mapped
+    Map(paging::Error),
+}
+
+/// The framebuffer and the cells drawn on it
+struct Screen {
+    /// the framebuffer is identity mapped
+    address: usize,
+    /// bytes per line
+    pitch: usize,
+    /// in pixels
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+    red: ColorField,
+    green: ColorField,
+    blue: ColorField,
+    palette: Palette,
+    /// pixel values of the 16 colors
+    colors: [u32; 16],
+    /// scanlines of a glyph, 0 until the first font is set
+    glyph_height: usize,
+    /// `glyph_height` scanlines for each of the 256 glyphs
+    glyphs: Vec<u8>,
+    scale: usize,
+    columns: usize,
+    rows: usize,
+    /// the characters on screen, row by row
+    cells: Vec<Character>,
+    cursor: usize,
+    cursor_enabled: bool,
+    cursor_shape: CursorShape,
+}
+
+static SCREEN: spin::Mutex<Option<Screen>> = spin::Mutex::new(None);
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// The dimensions, outside of `SCREEN` to be read without waiting for the lock
+static COLUMNS: AtomicUsize = AtomicUsize::new(0);
+static ROWS: AtomicUsize = AtomicUsize::new(0);
+
+impl Screen {
+    /// The pixel value of `rgb`
+    fn pixel(&self, rgb: Rgb) -> u32 {
+        let channel = |value: u8, field: ColorField| {
+            (value as u32 >> (8 - field.size.min(8))) << field.position
+        };
+        channel(rgb.r, self.red) | channel(rgb.g, self.green) | channel(rgb.b, self.blue)
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = *palette;
+        for (i, &rgb) in palette.0.iter().enumerate() {
+            self.colors[i] = self.pixel(rgb);
+        }
+    }
+
+    /// Picks the scale and the number of characters for glyphs of `glyph_height` scanlines
+    fn layout(&mut self, glyph_height: usize) -> Result<(), Error> {
+        let fits = |scale: usize| {
+            (
+                self.width / (8 * scale),
+                self.height / (glyph_height * scale),
+            )
+        };
+        let mut scale = 1;
+        while matches!(fits(scale + 1), (columns, rows) if columns >= 80 && rows >= 25) {
+            scale += 1;
+        }
+        let (columns, rows) = fits(scale);
+        if columns == 0 || rows == 0 {
+            return Err(Error::TooSmall);
+        }
+        self.glyph_height = glyph_height;
+        self.glyphs = vec![0; 256 * glyph_height];
+        self.scale = scale;
+        self.columns = columns;
+        self.rows = rows;
+        self.cells = vec![Character::blank(); columns * rows];
+        self.cursor = 0;
+        COLUMNS.store(columns, Ordering::Relaxed);
+        ROWS.store(rows, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Writes a pixel value at `address`
+    /// # Safety
+    /// `address` has to be a pixel in the framebuffer
+    unsafe fn write_pixel(&self, address: usize, value: u32) {
+        match self.bytes_per_pixel {
+            4 => (address as *mut u32).write_volatile(value),
+            3 => {
+                for i in 0..3 {
+                    (address as *mut u8)
+                        .add(i)
+                        .write_volatile((value >> (8 * i)) as u8);
+                }
+            }
+            _ => (address as *mut u16).write_volatile(value as u16),
+        }
+    }
+
+    /// Fills the whole screen with `color`, also the border the cells don't cover
+    fn fill(&self, color: u32) {
+        for y in 0..self.height {
+            let line = self.address + y * self.pitch;
+            for x in 0..self.width {
+                unsafe { self.write_pixel(line + x * self.bytes_per_pixel, color) };
+            }
+        }
+    }
+
+    /// First and last scanline of the glyph the cursor covers
+    fn cursor_scanlines(&self) -> (usize, usize) {
+        let last = self.glyph_height - 1;
+        match self.cursor_shape {
+            CursorShape::Underline => (last.saturating_sub(1), last),
+            CursorShape::HalfBlock => (self.glyph_height / 2, last),
+            CursorShape::Block => (0, last),
+            CursorShape::Scanlines(start, end) => {
+                ((start as usize).min(last), (end as usize).min(last))
+            }
+        }
+    }
+
+    /// Draws the character in cell `index`, with the cursor over it if it's there
+    fn draw(&self, index: usize) {
+        // nothing to draw before the first font
+        if index >= self.cells.len() {
+            return;
+        }
+        let c = self.cells[index];
+        let fore = self.colors[c.color().fore() as usize];
+        let back = self.colors[c.color().back() as usize];
+        let (cell_width, cell_height) = (8 * self.scale, self.glyph_height * self.scale);
+        let x = index % self.columns * cell_width;
+        let y = index / self.columns * cell_height;
+        let start = c.ascii() as usize * self.glyph_height;
+        let glyph = &self.glyphs[start..start + self.glyph_height];
+        let cursor = if self.cursor_enabled && self.cursor == index {
+            self.cursor_scanlines()
+        } else {
+            // empty
+            (1, 0)
+        };
+        for (scanline, &bits) in glyph.iter().enumerate() {
+            let bits = if (cursor.0..=cursor.1).contains(&scanline) {
+                0xFF
+            } else {
+                bits
+            };
+            for dy in 0..self.scale {
+                let line = self.address
+                    + (y + scanline * self.scale + dy) * self.pitch
+                    + x * self.bytes_per_pixel;
+                for dx in 0..cell_width {
+                    let value = if bits & 0x80 >> (dx / self.scale) != 0 {
+                        fore
+                    } else {
+                        back
+                    };
+                    unsafe { self.write_pixel(line + dx * self.bytes_per_pixel, value) };
+                }
+            }
+        }
+    }
+
+    fn redraw(&self) {
+        for index in 0..self.cells.len() {
+            self.draw(index);
+        }
+    }
+
+    /// Replaces the characters from cell `start` on, drawing those which changed
+    fn write(&mut self, start: usize, src: &[Character]) {
+        if start + src.len() > self.cells.len() {
+            panic!(
+                "framebuffer::write_at({}, {{len: {}}}): the screen has only {} cells",
+                start,
+                src.len(),
+                self.cells.len()
+            );
+        }
+        for (i, &c) in src.iter().enumerate() {
+            if self.cells[start + i] != c {
+                self.cells[start + i] = c;
+                self.draw(start + i);
+            }
+        }
+    }
+
+    fn set_cursor(&mut self, cursor: usize, enabled: bool) {
+        let previous = (self.cursor, self.cursor_enabled);
+        self.cursor = cursor;
+        self.cursor_enabled = enabled;
+        if previous != (cursor, enabled) {
+            self.draw(previous.0);
+            self.draw(cursor);
+        }
+    }
+}
+
+/// Takes over the framebuffer described by the Multiboot2 framebuffer tag.
+/// Nothing is drawn until a font is set, see `set_font`.
+pub fn init(info: &multiboot::Framebuffer) -> Result<(), Error> {
+    let (red, green, blue) = match info.kind {
+        FramebufferKind::Rgb { red, green, blue } => (red, green, blue),
+        _ => return Err(Error::Kind),
+    };
+    if !matches!(info.bpp, 16 | 24 | 32) {
+        return Err(Error::Bpp(info.bpp));
+    }
+    let length = info.pitch as usize * info.height as usize;
+    paging::mapper()
+        .lock()
+        .identity_map(info.address as usize, length, Flags::MMIO)
+        .map_err(Error::Map)?;
+    let mut screen = Screen {
+        address: info.address as usize,
+        pitch: info.pitch as usize,
+        width: info.width as usize,
+        height: info.height as usize,
+        bytes_per_pixel: info.bpp as usize / 8,
+        red,
+        green,
+        blue,
+        palette: Palette::VGA,
+        colors: [0; 16],
+        glyph_height: 0,
+        glyphs: Vec::new(),
+        scale: 1,
+        columns: 0,
+        rows: 0,
+        cells: Vec::new(),
+        cursor: 0,
+        cursor_enabled: false,
+        cursor_shape: CursorShape::Underline,
+    };
+    if screen.width < 8 || screen.height < MAX_HEIGHT {
+        return Err(Error::TooSmall);
+    }
+    screen.set_palette(&Palette::VGA);
+    screen.fill(screen.colors[0]);
+    *SCREEN.lock() = Some(screen);
+    ACTIVE.store(true, Ordering::Release);
+    log::info!(
+        "Initialized the framebuffer console, {}x{}x{} at 0x{:x}",
+        info.width,
+        info.height,
+        info.bpp,
+        info.address
+    );
+    Ok(())
+}
+
+/// Whether the TTY draws on the framebuffer instead of the VGA text buffer
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Runs `f` with the screen, panics if the console isn't initialized
+fn with_screen<R, F: FnOnce(&mut Screen) -> R>(f: F) -> R {
+    match SCREEN.lock().as_mut() {
+        Some(screen) => f(screen),
+        None => panic!("framebuffer: the framebuffer console isn't initialized"),
+    }
+}
+
+/// Columns and rows of characters, `(0, 0)` before the first font is set
+pub fn dimensions() -> (usize, usize) {
+    (
+        COLUMNS.load(Ordering::Relaxed),
+        ROWS.load(Ordering::Relaxed),
+    )
+}
+
+/// Draws `font`, scaled to the character height.
+/// The first font fixes the height and the number of characters on the screen,
+/// so it has to be set before the TTY is created.
+pub fn set_font(font: &Glyphs) {
+    with_screen(|screen| {
+        if screen.glyph_height == 0 {
+            if let Err(e) = screen.layout(font.height()) {
+                panic!(
+                    "framebuffer::set_font({{height: {}}}): {:?}",
+                    font.height(),
+                    e
+                );
+            }
+            log::info!(
+                "The framebuffer console shows {}x{} characters of {}x{} pixels",
+                screen.columns,
+                screen.rows,
+                8 * screen.scale,
+                screen.glyph_height * screen.scale
+            );
+        }
+        let height = screen.glyph_height;
+        for byte in 0..=255 {
+            let start = byte as usize * height;
+            font.scaled(byte, &mut screen.glyphs[start..start + height]);
+        }
+        screen.redraw();
+    });
+}
+
+/// Writes characters from cell `pos` on, panics if they don't fit
+pub fn write_at(pos: (usize, usize), src: &[Character]) {
+    with_screen(|screen| screen.write(pos.0 + pos.1 * screen.columns, src));
+}
+
+/// Writes a single character, panics if the position is invalid
+pub fn writechar(pos: (usize, usize), c: Character) {
+    write_at(pos, &[c]);
+}
+
+/// Like `write_at`, but gives up if the console is locked instead of waiting.
+/// Returns whether the characters were written.
+/// Meant for the fault handlers, which may have interrupted a writer.
+pub fn try_write_at(pos: (usize, usize), src: &[Character]) -> bool {
+    match SCREEN.try_lock().as_deref_mut() {
+        Some(Some(screen)) => {
+            screen.write(pos.0 + pos.1 * screen.columns, src);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Releases the lock of the console, so the panic handler can write to it.
+/// # Safety
+/// Whoever holds the lock must never continue, i.e. it's the panicking code
+pub unsafe fn force_unlock() {
+    if SCREEN.is_locked() {
+        SCREEN.force_unlock();
+    }
+}
+
+/// Moves the cursor to a position, panics if it's invalid
+pub fn set_cursor_pos(pos: (usize, usize)) {
+    with_screen(|screen| {
+        if pos.0 >= screen.columns || pos.1 >= screen.rows {
+            panic!(
+                "framebuffer::set_cursor_pos(({},{})): invalid position",
+                pos.0, pos.1
+            );
+        }
+        screen.set_cursor(pos.0 + pos.1 * screen.columns, screen.cursor_enabled);
+    });
+}
+
+/// Shows or hides the cursor
+pub fn set_cursor_enabled(enabled: bool) {
+    with_screen(|screen| screen.set_cursor(screen.cursor, enabled));
+}
+
+/// Whether the cursor is shown
+pub fn cursor_enabled() -> bool {
+    with_screen(|screen| screen.cursor_enabled)
+}
+
+/// Sets the scanlines of the glyph the cursor covers
+pub fn set_cursor_shape(shape: CursorShape) {
+    with_screen(|screen| {
+        screen.cursor_shape = shape;
+        screen.draw(screen.cursor);
+    });
+}
+
+/// The colors currently on screen
+pub fn palette() -> Palette {
+    with_screen(|screen| screen.palette)
+}
+
+/// Redraws the screen with the colors of `palette`
+pub fn set_palette(palette: &Palette) {
+    with_screen(|screen| {
+        if screen.palette != *palette {
+            screen.set_palette(palette);
+            screen.fill(screen.colors[0]);
+            screen.redraw();
+        }
+    });
+}
+
+/// Clears the screen
+pub fn reset() {
+    with_screen(|screen| {
+        screen.cells.fill(Character::blank());
+        screen.fill(screen.colors[0]);
+        screen.draw(screen.cursor);
+    });
+}
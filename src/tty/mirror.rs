@@ -0,0 +1,124 @@
+//! Copies what's written to a TTY onto a serial port, for running headless.
+//!
+//! The TTY hands over its output as it parses it: drawn characters go out as
+//! UTF-8, control characters and escape sequences as they came in, so the
+//! terminal on the other end follows along. Colors are sent as SGR sequences
+//! whenever the next character's color differs from the one sent last, which
+//! covers both SGR sequences and `TTY::set_color`.
+//! Characters written to a position, like with `put`, aren't mirrored.
+
+use super::ansi::{Action, Csi};
+use super::cp437;
+use super::vgatext::{Character, TextColor};
+use crate::serial::{self, Com, Writer};
+use core::fmt::Write;
+
+const ESC: u8 = 0x1B;
+
+pub struct Mirror {
+    com: Com,
+    /// the color the terminal draws with, `None` after a reset
+    sent: Option<TextColor>,
+}
+
+impl Mirror {
+    pub fn new(com: Com) -> Mirror {
+        Mirror { com, sent: None }
+    }
+
+    /// The port mirrored to
+    pub fn com(&self) -> Com {
+        self.com
+    }
+
+    fn send(&self, bytes: &[u8]) {
+        let _ = serial::write(self.com, bytes);
+    }
+
+    /// Switches the terminal to `color`
+    fn sync_color(&mut self, color: TextColor) {
+        if self.sent == Some(color) {
+            return;
+        }
+        // bright colors are 90-97 and 100-107
+        let sgr = |ansi: u8, base: u8| {
+            if ansi < 8 {
+                base + ansi
+            } else {
+                base + 60 + ansi - 8
+            }
+        };
+        let _ = write!(
+            Writer(self.com),
+            "\x1b[0;{};{}m",
+            sgr(color.fore().ansi(), 30),
+            sgr(color.back().ansi(), 40)
+        );
+        self.sent = Some(color);
+    }
+
+    /// Sends a character drawn at the cursor
+    pub fn glyph(&mut self, c: Character) {
+        self.sync_color(c.color());
+        let mut utf8 = [0; 4];
+        let ch = match c.ascii() {
+            // blank cells
+            0 => ' ',
+            byte => cp437::to_char(byte),
+        };
+        self.send(ch.encode_utf8(&mut utf8).as_bytes());
+    }
+
+    /// Sends anything but a drawn character, `color` is the TTY's current color.
+    /// SGR sequences are left out, the colors follow with the next character.
+    pub fn action(&mut self, action: &Action, color: TextColor) {
+        match *action {
+            Action::Print(_) => {}
+            // terminals on a serial line don't add the carriage return themselves
+            Action::Control(b'\n') => self.send(b"\r\n"),
+            Action::Control(byte) => self.send(&[byte]),
+            Action::Escape {
+                intermediate,
+                final_byte,
+            } => {
+                self.send(&[ESC]);
+                if let Some(intermediate) = intermediate {
+                    self.send(&[intermediate]);
+                }
+                self.send(&[final_byte]);
+                // a full reset also resets the colors
+                if intermediate.is_none() && final_byte == b'c' {
+                    self.sent = None;
+                }
+            }
+            Action::Csi(csi) if csi.final_byte == b'm' && csi.intermediate.is_none() => {}
+            Action::Csi(csi) => {
+                // erasing fills with the current background
+                self.sync_color(color);
+                self.csi(&csi);
+            }
+        }
+    }
+
+    fn csi(&self, csi: &Csi) {
+        let mut w = Writer(self.com);
+        let _ = w.write_str("\x1b[");
+        if let Some(private) = csi.private {
+            let _ = w.write_char(private as char);
+        }
+        for (i, param) in csi.params().iter().enumerate() {
+            let separator = if i == 0 { "" } else { ";" };
+            let _ = write!(w, "{}{}", separator, param);
+        }
+        if let Some(intermediate) = csi.intermediate {
+            let _ = w.write_char(intermediate as char);
+        }
+        let _ = w.write_char(csi.final_byte as char);
+    }
+
+    /// Resets the terminal, for when the TTY is reset
+    pub fn reset(&mut self) {
+        self.send(b"\x1bc");
+        self.sent = None;
+    }
+}
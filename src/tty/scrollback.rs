@@ -0,0 +1,113 @@
+//! The lines which scrolled off the top of a TTY.
+
+use super::vgatext::Character;
+use alloc::vec::Vec;
+
+/// A ring buffer of lines, once `depth` lines are stored the oldest is overwritten.
+/// Memory is only allocated as lines arrive.
+pub struct Scrollback {
+    cells: Vec<Character>,
+    width: usize,
+    depth: usize,
+    /// index of the oldest line
+    start: usize,
+}
+
+impl Scrollback {
+    /// Creates an empty history of `depth` lines, `width` characters each
+    pub const fn new(width: usize, depth: usize) -> Scrollback {
+        Scrollback {
+            cells: Vec::new(),
+            width,
+            depth,
+            start: 0,
+        }
+    }
+
+    /// Number of stored lines
+    pub fn len(&self) -> usize {
+        self.cells.len() / self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Maximum number of lines
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Appends `line`, which must be `width` characters long
+    pub fn push(&mut self, line: &[Character]) {
+        if line.len() != self.width {
+            panic!(
+                "Scrollback::push({{len: {}}}): line isn't {} characters wide",
+                line.len(),
+                self.width
+            );
+        }
+        if self.depth == 0 {
+            return;
+        }
+        if self.len() < self.depth {
+            self.cells.extend_from_slice(line);
+        } else {
+            let start = self.start * self.width;
+            self.cells[start..start + self.width].copy_from_slice(line);
+            self.start = (self.start + 1) % self.depth;
+        }
+    }
+
+    /// The `index`th oldest line
+    pub fn line(&self, index: usize) -> &[Character] {
+        if index >= self.len() {
+            panic!(
+                "Scrollback::line({}): only {} lines stored",
+                index,
+                self.len()
+            );
+        }
+        let start = (self.start + index) % self.len() * self.width;
+        &self.cells[start..start + self.width]
+    }
+
+    /// Iterates from the oldest to the newest line
+    pub fn lines(&self) -> impl Iterator<Item = &[Character]> + '_ {
+        (0..self.len()).map(move |i| self.line(i))
+    }
+
+    /// Changes the number of lines kept, dropping the oldest ones if it shrinks
+    pub fn set_depth(&mut self, depth: usize) {
+        let keep = self.len().min(depth);
+        let mut cells = Vec::with_capacity(keep * self.width);
+        for i in self.len() - keep..self.len() {
+            cells.extend_from_slice(self.line(i));
+        }
+        self.cells = cells;
+        self.start = 0;
+        self.depth = depth;
+    }
+
+    /// Changes the width of the lines, cutting or padding the stored ones with blanks
+    pub fn set_width(&mut self, width: usize) {
+        if width == self.width {
+            return;
+        }
+        let columns = width.min(self.width);
+        let mut cells = Vec::with_capacity(self.len() * width);
+        for line in self.lines() {
+            cells.extend_from_slice(&line[..columns]);
+            cells.resize(cells.len() + width - columns, Character::blank());
+        }
+        self.cells = cells;
+        self.start = 0;
+        self.width = width;
+    }
+
+    /// Removes all lines and frees their memory
+    pub fn clear(&mut self) {
+        self.cells = Vec::new();
+        self.start = 0;
+    }
+}
@@ -1,4 +1,8 @@
+pub mod font;
+pub mod palette;
+
 use crate::memio;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 /// VGA 4 Bit Colors
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -45,6 +49,31 @@ impl From<u8> for Color {
     }
 }
 
+impl Color {
+    /// The color for ANSI color `index` 0-15, where 8-15 are the bright variants
+    pub fn from_ansi(index: u8) -> Color {
+        // ANSI orders red, green, blue, VGA blue, green, red
+        const ANSI: [Color; 8] = [
+            Color::Black,
+            Color::Red,
+            Color::Green,
+            Color::Brown,
+            Color::Blue,
+            Color::Magenta,
+            Color::Cyan,
+            Color::LightGray,
+        ];
+        Color::from(ANSI[index as usize % 8] as u8 | (index & 8))
+    }
+
+    /// The ANSI color index 0-15
+    pub fn ansi(self) -> u8 {
+        let c = self as u8;
+        // swap the red and blue bits
+        (c & 0b1010) | (c & 1) << 2 | (c & 4) >> 2
+    }
+}
+
 /// VGA Color Point
 /// Consists of a 4 bit VGA foreground and background color
 #[repr(C)]
@@ -74,7 +103,7 @@ impl TextColor {
 
     /// The foreground of the Color
     pub fn fore(&self) -> Color {
-        Color::from(self.0)
+        Color::from(self.0 & 0xF)
     }
 
     /// The background of the Color
@@ -140,17 +169,351 @@ impl From<u8> for Character {
     }
 }
 
-/// The VGA display buffer height
-pub const HEIGHT: usize = 25;
-/// The VGA display buffer width
-pub const WIDTH: usize = 80;
+/// A text mode the VGA can be switched to with `set_mode`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// 80x25 characters of 9x16 pixels, the mode we boot in
+    Text80x25,
+    /// 80x50 characters of 9x8 pixels
+    Text80x50,
+    /// 90x60 characters of 8x8 pixels
+    Text90x60,
+    /// 40x25 characters of 9x16 pixels, at half the dot clock
+    Text40x25,
+}
 
-pub fn vga_init() {
+/// The registers which differ between the text modes
+struct Registers {
+    misc: u8,
+    /// sequencer clocking mode: 8 or 9 pixel wide characters, dot clock divider
+    clocking: u8,
+    crtc: [u8; 25],
+    /// attribute controller horizontal panning, 8 for 9 pixel wide characters
+    panning: u8,
+}
+
+#[rustfmt::skip]
+const REGISTERS_80X25: Registers = Registers {
+    misc: 0x67,
+    clocking: 0x00,
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F,
+        0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x00,
+        0x9C, 0x8E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    panning: 0x08,
+};
+
+#[rustfmt::skip]
+const REGISTERS_80X50: Registers = Registers {
+    misc: 0x67,
+    clocking: 0x00,
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F,
+        0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0x9C, 0x8E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    panning: 0x08,
+};
+
+/// 720x480 pixels, on the 28 MHz clock
+#[rustfmt::skip]
+const REGISTERS_90X60: Registers = Registers {
+    misc: 0xE7,
+    clocking: 0x01,
+    crtc: [
+        0x6B, 0x59, 0x5A, 0x82, 0x60, 0x8D, 0x0B, 0x3E,
+        0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0xEA, 0x0C, 0xDF, 0x2D, 0x08, 0xE8, 0x05, 0xA3, 0xFF,
+    ],
+    panning: 0x00,
+};
+
+#[rustfmt::skip]
+const REGISTERS_40X25: Registers = Registers {
+    misc: 0x67,
+    clocking: 0x08,
+    crtc: [
+        0x2D, 0x27, 0x28, 0x90, 0x2B, 0xA0, 0xBF, 0x1F,
+        0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x00,
+        0x9C, 0x8E, 0x8F, 0x14, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    panning: 0x08,
+};
+
+impl Mode {
+    /// All modes, in the order of their names
+    pub const ALL: [Mode; 4] = [
+        Mode::Text80x25,
+        Mode::Text80x50,
+        Mode::Text90x60,
+        Mode::Text40x25,
+    ];
+
+    /// Columns and rows
+    pub fn dimensions(self) -> (usize, usize) {
+        match self {
+            Mode::Text80x25 => (80, 25),
+            Mode::Text80x50 => (80, 50),
+            Mode::Text90x60 => (90, 60),
+            Mode::Text40x25 => (40, 25),
+        }
+    }
+
+    /// Height of a character cell in scanlines
+    pub fn font_height(self) -> u8 {
+        match self {
+            Mode::Text80x25 | Mode::Text40x25 => 16,
+            Mode::Text80x50 | Mode::Text90x60 => 8,
+        }
+    }
+
+    /// The mode called `name`, like "80x50"
+    pub fn from_name(name: &str) -> Option<Mode> {
+        Mode::ALL.iter().copied().find(|mode| {
+            let (width, height) = mode.dimensions();
+            let (w, h) = match name.split_once('x') {
+                Some(dimensions) => dimensions,
+                None => return false,
+            };
+            w.parse() == Ok(width) && h.parse() == Ok(height)
+        })
+    }
+
+    fn registers(self) -> &'static Registers {
+        match self {
+            Mode::Text80x25 => &REGISTERS_80X25,
+            Mode::Text80x50 => &REGISTERS_80X50,
+            Mode::Text90x60 => &REGISTERS_90X60,
+            Mode::Text40x25 => &REGISTERS_40X25,
+        }
+    }
+}
+
+/// Index of the active mode in `Mode::ALL`
+static MODE: AtomicU8 = AtomicU8::new(0);
+static WIDTH: AtomicUsize = AtomicUsize::new(80);
+static HEIGHT: AtomicUsize = AtomicUsize::new(25);
+
+/// The active text mode
+pub fn mode() -> Mode {
+    Mode::ALL[MODE.load(Ordering::Relaxed) as usize]
+}
+
+/// The VGA display buffer width of the active mode
+pub fn width() -> usize {
+    WIDTH.load(Ordering::Relaxed)
+}
+
+/// The VGA display buffer height of the active mode
+pub fn height() -> usize {
+    HEIGHT.load(Ordering::Relaxed)
+}
+
+/// Width and height of the active mode
+pub fn dimensions() -> (usize, usize) {
+    (width(), height())
+}
+
+const MISC_WRITE: u16 = 0x3C2;
+const SEQ_INDEX: u16 = 0x3C4;
+const SEQ_DATA: u16 = 0x3C5;
+const GC_INDEX: u16 = 0x3CE;
+const GC_DATA: u16 = 0x3CF;
+const AC_INDEX: u16 = 0x3C0;
+/// Reading it resets the attribute controller to expect an index
+const INPUT_STATUS: u16 = 0x3DA;
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+
+/// Attribute controller index bit which keeps the screen on
+const AC_PALETTE_SOURCE: u8 = 0x20;
+const AC_PANNING: u8 = 0x13;
+
+const SEQ_RESET: u8 = 0x00;
+const SEQ_CLOCKING: u8 = 0x01;
+const SEQ_MAP_MASK: u8 = 0x02;
+const SEQ_MEMORY_MODE: u8 = 0x04;
+const GC_READ_MAP: u8 = 0x04;
+const GC_MODE: u8 = 0x05;
+const GC_MISC: u8 = 0x06;
+
+const CRTC_VRETRACE_END: u8 = 0x11;
+/// Vertical retrace end register bit which write protects CRTC registers 0 to 7
+const CRTC_PROTECT: u8 = 0x80;
+const CRTC_HBLANK_END: u8 = 0x03;
+
+const CRTC_MAX_SCANLINE: u8 = 0x09;
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_END: u8 = 0x0B;
+const CRTC_CURSOR_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOW: u8 = 0x0F;
+
+/// Cursor start register bit which hides the cursor
+const CURSOR_DISABLE: u8 = 0x20;
+/// Bits of the cursor start and end registers holding the scanline
+const CURSOR_SCANLINE: u8 = 0x1F;
+
+fn crtc_read(reg: u8) -> u8 {
     unsafe {
-        // disable cursor
-        memio::outb(0x3D4, 0xA);
-        memio::outb(0x3D5, 0x20);
+        memio::outb(CRTC_INDEX, reg);
+        memio::inb(CRTC_DATA)
+    }
+}
 
+fn crtc_write(reg: u8, val: u8) {
+    unsafe {
+        memio::outb(CRTC_INDEX, reg);
+        memio::outb(CRTC_DATA, val);
+    }
+}
+
+fn seq_read(reg: u8) -> u8 {
+    unsafe {
+        memio::outb(SEQ_INDEX, reg);
+        memio::inb(SEQ_DATA)
+    }
+}
+
+fn seq_write(reg: u8, val: u8) {
+    unsafe {
+        memio::outb(SEQ_INDEX, reg);
+        memio::outb(SEQ_DATA, val);
+    }
+}
+
+fn gc_read(reg: u8) -> u8 {
+    unsafe {
+        memio::outb(GC_INDEX, reg);
+        memio::inb(GC_DATA)
+    }
+}
+
+fn gc_write(reg: u8, val: u8) {
+    unsafe {
+        memio::outb(GC_INDEX, reg);
+        memio::outb(GC_DATA, val);
+    }
+}
+
+fn ac_write(reg: u8, val: u8) {
+    unsafe {
+        memio::inb(INPUT_STATUS);
+        memio::outb(AC_INDEX, reg | AC_PALETTE_SOURCE);
+        memio::outb(AC_INDEX, val);
+    }
+}
+
+/// Programs the VGA for `mode` and loads a font of the mode's height.
+/// Video memory isn't cleared, the TTY has to repaint the screen afterwards,
+/// see `tty::set_mode`.
+pub fn set_mode(mode: Mode) {
+    let registers = mode.registers();
+    // the font has to be saved before the character height changes
+    font::save_boot_font();
+    unsafe {
+        seq_write(SEQ_RESET, 0x01);
+        memio::outb(MISC_WRITE, registers.misc);
+        seq_write(SEQ_CLOCKING, registers.clocking);
+        seq_write(SEQ_RESET, 0x03);
+    }
+    // unlock the timing registers and keep them unlocked
+    crtc_write(CRTC_HBLANK_END, crtc_read(CRTC_HBLANK_END) | 0x80);
+    crtc_write(
+        CRTC_VRETRACE_END,
+        crtc_read(CRTC_VRETRACE_END) & !CRTC_PROTECT,
+    );
+    for (reg, &val) in registers.crtc.iter().enumerate() {
+        let val = match reg as u8 {
+            CRTC_HBLANK_END => val | 0x80,
+            CRTC_VRETRACE_END => val & !CRTC_PROTECT,
+            _ => val,
+        };
+        crtc_write(reg as u8, val);
+    }
+    ac_write(AC_PANNING, registers.panning);
+    font::reload();
+
+    let (width, height) = mode.dimensions();
+    WIDTH.store(width, Ordering::Relaxed);
+    HEIGHT.store(height, Ordering::Relaxed);
+    let index = Mode::ALL.iter().position(|&m| m == mode).unwrap_or(0);
+    MODE.store(index as u8, Ordering::Relaxed);
+    log::info!("Switched the VGA to {}x{} text mode", width, height);
+}
+
+/// The shape of the hardware text cursor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorShape {
+    /// The bottom two scanlines
+    Underline,
+    /// The lower half of the character cell
+    HalfBlock,
+    /// The whole character cell
+    Block,
+    /// From the first to the second scanline, inclusive
+    Scanlines(u8, u8),
+}
+
+/// Height of a character cell in scanlines
+pub fn font_height() -> u8 {
+    (crtc_read(CRTC_MAX_SCANLINE) & CURSOR_SCANLINE) + 1
+}
+
+/// Sets the scanlines the cursor covers
+pub fn set_cursor_shape(shape: CursorShape) {
+    let height = font_height();
+    let last = height - 1;
+    let (start, end) = match shape {
+        CursorShape::Underline => (last.saturating_sub(1), last),
+        CursorShape::HalfBlock => (height / 2, last),
+        CursorShape::Block => (0, last),
+        CursorShape::Scanlines(start, end) => (start.min(last), end.min(last)),
+    };
+    // keep the disable bit and the reserved and skew bits
+    crtc_write(
+        CRTC_CURSOR_START,
+        crtc_read(CRTC_CURSOR_START) & !CURSOR_SCANLINE | start,
+    );
+    crtc_write(
+        CRTC_CURSOR_END,
+        crtc_read(CRTC_CURSOR_END) & !CURSOR_SCANLINE | end,
+    );
+}
+
+/// Shows or hides the cursor
+pub fn set_cursor_enabled(enabled: bool) {
+    let start = crtc_read(CRTC_CURSOR_START);
+    crtc_write(
+        CRTC_CURSOR_START,
+        if enabled {
+            start & !CURSOR_DISABLE
+        } else {
+            start | CURSOR_DISABLE
+        },
+    );
+}
+
+/// Whether the cursor is shown
+pub fn cursor_enabled() -> bool {
+    crtc_read(CRTC_CURSOR_START) & CURSOR_DISABLE == 0
+}
+
+/// Moves the cursor to a position, panics if it's invalid
+pub fn set_cursor_pos(pos: (usize, usize)) {
+    if pos.0 >= width() || pos.1 >= height() {
+        panic!("set_cursor_pos(({},{})): invalid position", pos.0, pos.1);
+    }
+    let offset = (pos.0 + pos.1 * width()) as u16;
+    crtc_write(CRTC_CURSOR_HIGH, (offset >> 8) as u8);
+    crtc_write(CRTC_CURSOR_LOW, offset as u8);
+}
+
+/// Hides the cursor and disables blinking, the TTY shows the cursor on its first flush
+pub fn vga_init() {
+    set_cursor_enabled(false);
+    set_cursor_shape(CursorShape::Underline);
+    unsafe {
         // disable blinking
         memio::inb(0x3DA);
         memio::outb(0x3C0, 0x30);
@@ -164,11 +527,11 @@ pub fn vga_init() {
 /// # Safety
 /// Validate that the position is valid, and that the characters fit
 pub unsafe fn write_at(pos: (usize, usize), src: &[Character]) {
-    crate::memio::vmemwrite(
-        (0xb8000 + (pos.0 + pos.1 * WIDTH) * 2) as u64,
-        crate::memio::cast_slice(src),
-        src.len() * 2,
-    )
+    // whole characters at once, half as many VRAM accesses as byte writes
+    let baseaddr = (0xb8000 + (pos.0 + pos.1 * width()) * 2) as *mut Character;
+    for (i, &c) in src.iter().enumerate() {
+        baseaddr.add(i).write_volatile(c);
+    }
 }
 
 /// Write a slice of ascii characters, all of the same specified color,
@@ -177,7 +540,7 @@ pub unsafe fn write_at(pos: (usize, usize), src: &[Character]) {
 /// Validate that the position is in bounds, and that the string fits
 pub unsafe fn write_color_at(pos: (usize, usize), src: &[u8], color: TextColor) {
     let color = color.into();
-    let baseaddr = (0xb8000 + (pos.0 + pos.1 * WIDTH) * 2) as *mut u8;
+    let baseaddr = (0xb8000 + (pos.0 + pos.1 * width()) * 2) as *mut u8;
 
     for (i, &b) in src.iter().enumerate() {
         baseaddr.add(2 * i).write_volatile(b);
@@ -189,13 +552,13 @@ pub unsafe fn write_color_at(pos: (usize, usize), src: &[u8], color: TextColor)
 /// # Safety
 /// Validate that he position is valid
 pub unsafe fn writechar(pos: (usize, usize), char: Character) {
-    memio::vwrite((0xb8000 + (pos.0 + pos.1 * WIDTH) * 2) as u64, &char);
+    memio::vwrite((0xb8000 + (pos.0 + pos.1 * width()) * 2) as u64, &char);
 }
 
 /// Reset the video memory
 pub fn reset() {
     unsafe {
-        memio::vmemset(0xb8000, 0, WIDTH * HEIGHT * 2);
+        memio::vmemset(0xb8000, 0, width() * height() * 2);
     }
 }
 
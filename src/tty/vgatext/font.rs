@@ -0,0 +1,495 @@
+//! The font of the text modes, stored in plane 2 of video memory.
+//!
+//! A font has 256 glyphs in CP437 order, 8 pixels wide and up to 32 scanlines
+//! high, one byte per scanline. Fonts of another height than the text mode's
+//! are scaled. PSF fonts with a Unicode table are rearranged into CP437 order,
+//! those without are expected to be in it already.
+//!
+//! The framebuffer console has no font in video memory, it starts with the
+//! 8x8 `builtin` font and gets every font loaded here instead of plane 2.
+//!
+//! Once `cp437::set_czech` is enabled, the Czech and Slovak letters take the
+//! place of rarely used glyphs, see `cp437::CZECH`. Fonts which lack them get
+//! them composed from the base letter and its diacritic.
+
+use super::{
+    font_height, gc_read, gc_write, seq_read, seq_write, GC_MISC, GC_MODE, GC_READ_MAP,
+    SEQ_MAP_MASK, SEQ_MEMORY_MODE,
+};
+use crate::tty::{cp437, framebuffer};
+
+mod builtin;
+
+/// The highest glyph, also the bytes every glyph takes in plane 2
+pub const MAX_HEIGHT: usize = 32;
+
+/// A font borrowed from e.g. an embedded PSF file
+pub struct Font<'a> {
+    height: usize,
+    count: usize,
+    glyphs: &'a [u8],
+    unicode: Option<Unicode<'a>>,
+}
+
+/// The Unicode table of a PSF font
+enum Unicode<'a> {
+    /// UCS-2 entries, 0xFFFF ends a glyph and 0xFFFE starts its sequences
+    Psf1(&'a [u8]),
+    /// UTF-8 strings, 0xFF ends a glyph and 0xFE starts its sequences
+    Psf2(&'a [u8]),
+}
+
+/// Why a PSF font can't be used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PsfError {
+    /// Neither a PSF1 nor a PSF2 file
+    Magic,
+    /// The file ends before its glyphs or Unicode table do
+    Truncated,
+    /// Glyphs wider than 8 pixels
+    Width(u32),
+    /// Glyphs of no or more than `MAX_HEIGHT` scanlines
+    Height(u32),
+    /// Fewer than 256 glyphs in a font without a Unicode table
+    Glyphs(u32),
+}
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_UNICODE: u8 = 0x02;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_FLAG_UNICODE: u32 = 0x01;
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, PsfError> {
+    let bytes = data.get(offset..offset + 4).ok_or(PsfError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+impl<'a> Font<'a> {
+    /// A font of 256 glyphs in CP437 order, `height` bytes each
+    pub fn new(height: usize, glyphs: &'a [u8]) -> Font<'a> {
+        if height == 0 || height > MAX_HEIGHT || glyphs.len() < 256 * height {
+            panic!(
+                "Font::new({}, {{len: {}}}): invalid font",
+                height,
+                glyphs.len()
+            );
+        }
+        Font {
+            height,
+            count: 256,
+            glyphs,
+            unicode: None,
+        }
+    }
+
+    /// Parses a PSF1 or PSF2 font, like one embedded with `include_bytes!`
+    pub fn from_psf(data: &'a [u8]) -> Result<Font<'a>, PsfError> {
+        let (height, count, glyphs_start, unicode) = if data.starts_with(&PSF1_MAGIC) {
+            let mode = *data.get(2).ok_or(PsfError::Truncated)?;
+            let height = *data.get(3).ok_or(PsfError::Truncated)? as u32;
+            let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+            (height, count, 4, mode & PSF1_MODE_UNICODE != 0)
+        } else if data.starts_with(&PSF2_MAGIC) {
+            let header_size = read_u32(data, 8)? as usize;
+            let flags = read_u32(data, 12)?;
+            let count = read_u32(data, 16)?;
+            let glyph_size = read_u32(data, 20)?;
+            let height = read_u32(data, 24)?;
+            let width = read_u32(data, 28)?;
+            if width > 8 {
+                return Err(PsfError::Width(width));
+            }
+            if glyph_size != height {
+                return Err(PsfError::Height(height));
+            }
+            (height, count, header_size, flags & PSF2_FLAG_UNICODE != 0)
+        } else {
+            return Err(PsfError::Magic);
+        };
+        if height == 0 || height as usize > MAX_HEIGHT {
+            return Err(PsfError::Height(height));
+        }
+        if !unicode && count < 256 {
+            return Err(PsfError::Glyphs(count));
+        }
+        let glyphs_end = glyphs_start + count as usize * height as usize;
+        let glyphs = data
+            .get(glyphs_start..glyphs_end)
+            .ok_or(PsfError::Truncated)?;
+        let table = &data[glyphs_end..];
+        Ok(Font {
+            height: height as usize,
+            count: count as usize,
+            glyphs,
+            unicode: match (unicode, data[0]) {
+                (false, _) => None,
+                (true, 0x36) => Some(Unicode::Psf1(table)),
+                (true, _) => Some(Unicode::Psf2(table)),
+            },
+        })
+    }
+
+    /// Scanlines of every glyph
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of glyphs, which may be more than 256 for PSF fonts
+    pub fn glyph_count(&self) -> usize {
+        self.count
+    }
+
+    /// The scanlines of glyph `index`, panics if there is no such glyph
+    pub fn glyph(&self, index: usize) -> &'a [u8] {
+        if index >= self.count {
+            panic!(
+                "Font::glyph({}): the font has only {} glyphs",
+                index, self.count
+            );
+        }
+        &self.glyphs[index * self.height..(index + 1) * self.height]
+    }
+
+    /// Index of the glyph for `c` according to the Unicode table
+    pub fn find(&self, c: char) -> Option<usize> {
+        let index = match self.unicode {
+            None => None,
+            Some(Unicode::Psf1(table)) => {
+                let mut index = 0;
+                let mut in_sequences = false;
+                for entry in table.chunks_exact(2) {
+                    match u16::from_le_bytes([entry[0], entry[1]]) {
+                        0xFFFF => {
+                            index += 1;
+                            in_sequences = false;
+                        }
+                        0xFFFE => in_sequences = true,
+                        code if !in_sequences && code as u32 == c as u32 => return Some(index),
+                        _ => {}
+                    }
+                }
+                None
+            }
+            Some(Unicode::Psf2(table)) => table.split(|&b| b == 0xFF).position(|glyph| {
+                // sequences of several characters follow the 0xFE
+                let singles = glyph.split(|&b| b == 0xFE).next().unwrap_or(&[]);
+                matches!(core::str::from_utf8(singles), Ok(s) if s.contains(c))
+            }),
+        };
+        index.filter(|&index| index < self.count)
+    }
+
+    /// The glyph drawn for the CP437 `byte`
+    fn cp437_glyph(&self, byte: u8) -> Option<&'a [u8]> {
+        match self.unicode {
+            None => Some(self.glyph(byte as usize)),
+            Some(_) => self
+                .find(cp437::to_char(byte))
+                .map(|index| self.glyph(index)),
+        }
+    }
+}
+
+/// The 8x8 font the framebuffer console starts with, which may be loaded in text mode too
+pub fn builtin() -> Font<'static> {
+    Font::new(8, &builtin::GLYPHS)
+}
+
+/// The PSF font named by the `TTY_FONT` environment variable at build time,
+/// loaded by `tty::init`
+#[cfg(feature = "embedded-font")]
+pub static EMBEDDED: &[u8] = include_bytes!(env!("TTY_FONT"));
+
+/// A font as it's kept in memory, to scale it when the text mode changes
+pub struct Glyphs {
+    height: usize,
+    /// `MAX_HEIGHT` bytes for every glyph
+    glyphs: [u8; 256 * MAX_HEIGHT],
+}
+
+impl Glyphs {
+    const fn empty() -> Glyphs {
+        Glyphs {
+            height: 0,
+            glyphs: [0; 256 * MAX_HEIGHT],
+        }
+    }
+
+    /// Scanlines of every glyph
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The scanlines of the glyph of `byte`
+    pub fn glyph(&self, byte: u8) -> &[u8] {
+        let start = byte as usize * MAX_HEIGHT;
+        &self.glyphs[start..start + self.height]
+    }
+
+    /// Scales the glyph of `byte` to `to.len()` scanlines
+    pub fn scaled(&self, byte: u8, to: &mut [u8]) {
+        scale(self.glyph(byte), to);
+    }
+
+    fn glyph_mut(&mut self, byte: u8) -> &mut [u8] {
+        let start = byte as usize * MAX_HEIGHT;
+        &mut self.glyphs[start..start + self.height]
+    }
+
+    /// Takes over the glyphs of `font`, returns which bytes it has no glyph for.
+    /// Those are left blank.
+    fn copy_from(&mut self, font: &Font) -> [bool; 256] {
+        self.height = font.height();
+        let mut missing = [false; 256];
+        for byte in 0..=255 {
+            let glyph = font.cp437_glyph(byte);
+            missing[byte as usize] = glyph.is_none();
+            let rows = self.glyph_mut(byte);
+            match glyph {
+                Some(glyph) => rows.copy_from_slice(glyph),
+                None => rows.fill(0),
+            }
+        }
+        missing
+    }
+}
+
+/// The font found in plane 2 at boot
+static BOOT: spin::Mutex<Glyphs> = spin::Mutex::new(Glyphs::empty());
+/// The font loaded last, at its own height
+static CURRENT: spin::Mutex<Glyphs> = spin::Mutex::new(Glyphs::empty());
+
+/// Runs `f` with plane 2, which holds the font, mapped to 0xA0000
+fn with_font_plane<R, F: FnOnce(*mut u8) -> R>(f: F) -> R {
+    let map_mask = seq_read(SEQ_MAP_MASK);
+    let memory_mode = seq_read(SEQ_MEMORY_MODE);
+    let read_map = gc_read(GC_READ_MAP);
+    let gc_mode = gc_read(GC_MODE);
+    let gc_misc = gc_read(GC_MISC);
+    // plane 2 only, sequential addressing, 64 KiB at 0xA0000
+    seq_write(SEQ_MAP_MASK, 0x04);
+    seq_write(SEQ_MEMORY_MODE, 0x06);
+    gc_write(GC_READ_MAP, 0x02);
+    gc_write(GC_MODE, 0x00);
+    gc_write(GC_MISC, 0x04);
+    let result = f(0xA0000 as *mut u8);
+    seq_write(SEQ_MAP_MASK, map_mask);
+    seq_write(SEQ_MEMORY_MODE, memory_mode);
+    gc_write(GC_READ_MAP, read_map);
+    gc_write(GC_MODE, gc_mode);
+    gc_write(GC_MISC, gc_misc);
+    result
+}
+
+/// Scales the scanlines of `from` into `to`, scanlines which merge are ORed
+fn scale(from: &[u8], to: &mut [u8]) {
+    let (from_height, to_height) = (from.len(), to.len());
+    if to_height >= from_height {
+        for (i, row) in to.iter_mut().enumerate() {
+            *row = from[i * from_height / to_height];
+        }
+    } else {
+        to.fill(0);
+        for (i, &row) in from.iter().enumerate() {
+            to[i * to_height / from_height] |= row;
+        }
+    }
+}
+
+/// Writes `font` to plane 2, scaled to the character height of the text mode,
+/// or hands it to the framebuffer console
+fn write(font: &Glyphs) {
+    if framebuffer::is_active() {
+        framebuffer::set_font(font);
+        return;
+    }
+    let height = font_height() as usize;
+    let mut glyph = [0; MAX_HEIGHT];
+    with_font_plane(|plane| {
+        for byte in 0..=255 {
+            scale(font.glyph(byte), &mut glyph[..height]);
+            for (row, &val) in glyph.iter().enumerate() {
+                let offset = byte as usize * MAX_HEIGHT + row;
+                unsafe { plane.add(offset).write_volatile(val) };
+            }
+        }
+    });
+}
+
+/// Saves the font in plane 2 the first time it's called.
+/// Has to happen before the character height changes.
+pub(super) fn save_boot_font() {
+    let mut boot = BOOT.lock();
+    if boot.height != 0 {
+        return;
+    }
+    if framebuffer::is_active() {
+        boot.copy_from(&builtin());
+    } else {
+        boot.height = font_height() as usize;
+        with_font_plane(|plane| {
+            for (i, row) in boot.glyphs.iter_mut().enumerate() {
+                *row = unsafe { plane.add(i).read_volatile() };
+            }
+        });
+    }
+    let mut current = CURRENT.lock();
+    if current.height == 0 {
+        current.height = boot.height;
+        current.glyphs = boot.glyphs;
+    }
+}
+
+/// Writes the current font again, scaled to a new character height
+pub(super) fn reload() {
+    save_boot_font();
+    write(&CURRENT.lock());
+}
+
+/// The diacritic of a composed letter
+#[derive(Clone, Copy)]
+enum Mark {
+    Caron,
+    Acute,
+    Ring,
+    /// the caron of "ď", "ľ" and "ť", beside the ascender
+    Apostrophe,
+}
+
+impl Mark {
+    /// Scanlines of the mark, a compact variant if there's little `room`:
+    /// the scanlines above the letter, or the glyph height for `Mark::Apostrophe`
+    fn rows(self, room: usize) -> &'static [u8] {
+        match self {
+            Mark::Caron => &[0x6C, 0x38],
+            Mark::Acute => &[0x0C, 0x18],
+            Mark::Ring if room >= 3 => &[0x38, 0x6C, 0x38],
+            Mark::Ring => &[0x38, 0x38],
+            Mark::Apostrophe if room >= 12 => &[0x06, 0x06, 0x0C],
+            Mark::Apostrophe => &[0x06, 0x0C],
+        }
+    }
+
+    /// The mark and base letter of `letter`
+    fn of(letter: char) -> Option<(Mark, char)> {
+        let mark = match letter {
+            'č' | 'ě' | 'ň' | 'ř' | 'š' | 'ž' => Mark::Caron,
+            'Č' | 'Ď' | 'Ě' | 'Ň' | 'Ř' | 'Š' | 'Ť' | 'Ž' => Mark::Caron,
+            'ĺ' | 'ŕ' | 'ý' => Mark::Acute,
+            'ů' | 'Ů' => Mark::Ring,
+            'ď' | 'ľ' | 'ť' | 'Ľ' => Mark::Apostrophe,
+            _ => return None,
+        };
+        Some((mark, cp437::base_letter(letter)?))
+    }
+}
+
+/// Moves the letter in `glyph` down to start at scanline `to`, squeezed into fewer
+/// scanlines, and returns its new top. Makes room for a mark above capitals in low fonts.
+fn squeeze(glyph: &mut [u8], top: usize, to: usize) -> usize {
+    let bottom = glyph.iter().rposition(|&row| row != 0).map_or(0, |i| i + 1);
+    // too little of the letter would be left
+    if bottom < to + 3 {
+        return top;
+    }
+    let mut letter = [0; MAX_HEIGHT];
+    letter[..bottom - top].copy_from_slice(&glyph[top..bottom]);
+    glyph[..bottom].fill(0);
+    scale(&letter[..bottom - top], &mut glyph[to..bottom]);
+    to
+}
+
+/// Draws `mark` over `glyph`: above the letter, with a scanline between them if there's room,
+/// or beside the ascender for `Mark::Apostrophe`. Letters without room above are squeezed.
+fn compose(glyph: &mut [u8], mark: Mark) {
+    let top = glyph.iter().position(|&row| row != 0).unwrap_or(0);
+    let (rows, start) = if let Mark::Apostrophe = mark {
+        let rows = mark.rows(glyph.len());
+        (rows, top)
+    } else {
+        let rows = mark.rows(top);
+        let top = if top < rows.len() {
+            squeeze(glyph, top, rows.len())
+        } else {
+            top
+        };
+        let start = if top > rows.len() {
+            top - rows.len() - 1
+        } else {
+            top.saturating_sub(rows.len())
+        };
+        (rows, start)
+    };
+    for (row, &val) in glyph[start..].iter_mut().zip(rows) {
+        *row |= val;
+    }
+}
+
+/// Fills the slots of the Czech and Slovak letters the font has no glyph for
+fn compose_czech(font: &mut Glyphs, missing: impl Fn(u8) -> bool) {
+    let mut glyph = [0; MAX_HEIGHT];
+    for &(byte, letter) in cp437::CZECH.iter() {
+        if !missing(byte) {
+            continue;
+        }
+        if let Some((mark, base)) = Mark::of(letter) {
+            let glyph = &mut glyph[..font.height];
+            glyph.copy_from_slice(font.glyph(base as u8));
+            compose(glyph, mark);
+            font.glyph_mut(byte).copy_from_slice(glyph);
+        }
+    }
+}
+
+/// Loads `font`, replacing the current one. Glyphs it has none for are left blank.
+pub fn load(font: &Font) {
+    save_boot_font();
+    let mut current = CURRENT.lock();
+    let missing = current.copy_from(font);
+    if cp437::czech() {
+        // fonts without a Unicode table hold the glyphs CP437 has in the Czech slots
+        let has_unicode = font.unicode.is_some();
+        compose_czech(&mut current, |byte| !has_unicode || missing[byte as usize]);
+    }
+    write(&current);
+}
+
+/// Loads the font we booted with again
+pub fn load_boot_font() {
+    save_boot_font();
+    let boot = BOOT.lock();
+    let mut current = CURRENT.lock();
+    current.height = boot.height;
+    current.glyphs = boot.glyphs;
+    if cp437::czech() {
+        compose_czech(&mut current, |_| true);
+    }
+    write(&current);
+}
+
+/// Draws the Czech and Slovak letters of `cp437::CZECH` in place of the glyphs they replace
+/// and makes the UTF-8 translation use them
+pub fn load_czech() {
+    save_boot_font();
+    cp437::set_czech(true);
+    let mut current = CURRENT.lock();
+    compose_czech(&mut current, |_| true);
+    write(&current);
+}
+
+/// Replaces the glyph of `byte`, `rows` are its scanlines.
+/// Glyphs of another height than the current font's are scaled.
+pub fn set_glyph(byte: u8, rows: &[u8]) {
+    if rows.is_empty() || rows.len() > MAX_HEIGHT {
+        panic!(
+            "set_glyph({}, {{len: {}}}): invalid glyph",
+            byte,
+            rows.len()
+        );
+    }
+    save_boot_font();
+    let mut current = CURRENT.lock();
+    scale(rows, current.glyph_mut(byte));
+    write(&current);
+}
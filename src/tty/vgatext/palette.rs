@@ -0,0 +1,233 @@
+//! The colors behind `Color`.
+//!
+//! Each of the 16 colors is an attribute controller palette register, which
+//! selects one of the first 64 entries of the DAC, which holds the RGB value.
+//! `set_palette` points the registers at DAC entries 0 to 15 and writes the
+//! colors there, so a `Palette` describes the screen completely.
+
+use super::{memio, Color, AC_INDEX, AC_PALETTE_SOURCE, INPUT_STATUS};
+
+const AC_DATA_READ: u16 = 0x3C1;
+const DAC_READ_INDEX: u16 = 0x3C7;
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+
+/// A color with 8 bits per channel, the DAC keeps the upper 6
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    /// From `0xRRGGBB`
+    pub const fn from_hex(hex: u32) -> Rgb {
+        Rgb::new((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+    }
+
+    /// Squared distance to `other`, weighted for how the eye perceives the channels
+    fn distance(self, other: Rgb) -> u32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+        2 * d(self.r, other.r) + 4 * d(self.g, other.g) + 3 * d(self.b, other.b)
+    }
+}
+
+/// The RGB values of the 16 colors, indexed by `Color as usize`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette(pub [Rgb; 16]);
+
+/// Amber at `level` out of 255, for `Palette::AMBER`
+const fn amber(level: u32) -> Rgb {
+    Rgb::new(level as u8, (level * 0xB0 / 0xFF) as u8, 0)
+}
+
+impl Palette {
+    /// The colors the VGA boots with
+    pub const VGA: Palette = Palette([
+        Rgb::from_hex(0x000000),
+        Rgb::from_hex(0x0000AA),
+        Rgb::from_hex(0x00AA00),
+        Rgb::from_hex(0x00AAAA),
+        Rgb::from_hex(0xAA0000),
+        Rgb::from_hex(0xAA00AA),
+        Rgb::from_hex(0xAA5500),
+        Rgb::from_hex(0xAAAAAA),
+        Rgb::from_hex(0x555555),
+        Rgb::from_hex(0x5555FF),
+        Rgb::from_hex(0x55FF55),
+        Rgb::from_hex(0x55FFFF),
+        Rgb::from_hex(0xFF5555),
+        Rgb::from_hex(0xFF55FF),
+        Rgb::from_hex(0xFFFF55),
+        Rgb::from_hex(0xFFFFFF),
+    ]);
+
+    /// Solarized dark, with the usual terminal assignment of its colors
+    pub const SOLARIZED: Palette = Palette([
+        Rgb::from_hex(0x073642),
+        Rgb::from_hex(0x268BD2),
+        Rgb::from_hex(0x859900),
+        Rgb::from_hex(0x2AA198),
+        Rgb::from_hex(0xDC322F),
+        Rgb::from_hex(0xD33682),
+        Rgb::from_hex(0xB58900),
+        Rgb::from_hex(0xEEE8D5),
+        Rgb::from_hex(0x002B36),
+        Rgb::from_hex(0x839496),
+        Rgb::from_hex(0x586E75),
+        Rgb::from_hex(0x93A1A1),
+        Rgb::from_hex(0xCB4B16),
+        Rgb::from_hex(0x6C71C4),
+        Rgb::from_hex(0x657B83),
+        Rgb::from_hex(0xFDF6E3),
+    ]);
+
+    /// Bright, saturated colors which all stand out against black
+    pub const HIGH_CONTRAST: Palette = Palette([
+        Rgb::from_hex(0x000000),
+        Rgb::from_hex(0x5C5CFF),
+        Rgb::from_hex(0x00E000),
+        Rgb::from_hex(0x00E0E0),
+        Rgb::from_hex(0xFF3030),
+        Rgb::from_hex(0xFF40FF),
+        Rgb::from_hex(0xFFC000),
+        Rgb::from_hex(0xFFFFFF),
+        Rgb::from_hex(0x909090),
+        Rgb::from_hex(0x8080FF),
+        Rgb::from_hex(0x00FF00),
+        Rgb::from_hex(0x00FFFF),
+        Rgb::from_hex(0xFF6060),
+        Rgb::from_hex(0xFF80FF),
+        Rgb::from_hex(0xFFFF00),
+        Rgb::from_hex(0xFFFFFF),
+    ]);
+
+    /// An amber monochrome monitor, colors become shades by their brightness
+    pub const AMBER: Palette = Palette([
+        amber(0x00),
+        amber(0x60),
+        amber(0x80),
+        amber(0x90),
+        amber(0x70),
+        amber(0x80),
+        amber(0x98),
+        amber(0xC0),
+        amber(0x50),
+        amber(0xB0),
+        amber(0xD0),
+        amber(0xE0),
+        amber(0xC0),
+        amber(0xD0),
+        amber(0xF0),
+        amber(0xFF),
+    ]);
+
+    /// The theme called `name`: "vga", "solarized", "high-contrast" or "amber"
+    pub fn from_name(name: &str) -> Option<Palette> {
+        Some(match name {
+            "vga" => Palette::VGA,
+            "solarized" => Palette::SOLARIZED,
+            "high-contrast" => Palette::HIGH_CONTRAST,
+            "amber" => Palette::AMBER,
+            _ => return None,
+        })
+    }
+
+    pub fn color(&self, color: Color) -> Rgb {
+        self.0[color as usize]
+    }
+
+    pub fn set_color(&mut self, color: Color, rgb: Rgb) {
+        self.0[color as usize] = rgb;
+    }
+
+    /// The color which looks closest to `rgb`
+    pub fn nearest(&self, rgb: Rgb) -> Color {
+        let index = (0..16)
+            .min_by_key(|&i| self.0[i].distance(rgb))
+            .unwrap_or(0);
+        Color::from(index as u8)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::VGA
+    }
+}
+
+/// Reads DAC entry `index`
+pub fn read_dac(index: u8) -> Rgb {
+    let scale = |v: u8| v << 2 | v >> 4;
+    unsafe {
+        memio::outb(DAC_READ_INDEX, index);
+        let r = memio::inb(DAC_DATA) & 0x3F;
+        let g = memio::inb(DAC_DATA) & 0x3F;
+        let b = memio::inb(DAC_DATA) & 0x3F;
+        Rgb::new(scale(r), scale(g), scale(b))
+    }
+}
+
+/// Writes DAC entry `index`
+pub fn write_dac(index: u8, rgb: Rgb) {
+    unsafe {
+        memio::outb(DAC_WRITE_INDEX, index);
+        memio::outb(DAC_DATA, rgb.r >> 2);
+        memio::outb(DAC_DATA, rgb.g >> 2);
+        memio::outb(DAC_DATA, rgb.b >> 2);
+    }
+}
+
+/// The DAC entry `color` is drawn with
+pub fn attribute(color: Color) -> u8 {
+    unsafe {
+        memio::inb(INPUT_STATUS);
+        memio::outb(AC_INDEX, color as u8 | AC_PALETTE_SOURCE);
+        memio::inb(AC_DATA_READ) & 0x3F
+    }
+}
+
+/// Writes the palette registers from `first` on
+fn write_attributes(first: u8, dacs: &[u8]) {
+    unsafe {
+        // the palette registers can only be written with the screen off
+        memio::inb(INPUT_STATUS);
+        for (i, &dac) in dacs.iter().enumerate() {
+            memio::outb(AC_INDEX, first + i as u8);
+            memio::outb(AC_INDEX, dac);
+        }
+        memio::outb(AC_INDEX, AC_PALETTE_SOURCE);
+    }
+}
+
+/// Draws `color` with DAC entry `dac`, one of the first 64
+pub fn set_attribute(color: Color, dac: u8) {
+    if dac >= 64 {
+        panic!("set_attribute({:?}, {}): invalid DAC entry", color, dac);
+    }
+    write_attributes(color as u8, &[dac]);
+}
+
+/// The colors currently on screen
+pub fn palette() -> Palette {
+    let mut palette = Palette::VGA;
+    for (i, rgb) in palette.0.iter_mut().enumerate() {
+        *rgb = read_dac(attribute(Color::from(i as u8)));
+    }
+    palette
+}
+
+/// Shows the colors of `palette`
+pub fn set_palette(palette: &Palette) {
+    let mut dacs = [0; 16];
+    for (i, &rgb) in palette.0.iter().enumerate() {
+        dacs[i] = i as u8;
+        write_dac(i as u8, rgb);
+    }
+    write_attributes(0, &dacs);
+}
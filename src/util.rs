@@ -1,2 +1,5 @@
+pub mod bytes;
 pub mod iterators;
-pub mod text;
\ No newline at end of file
+pub mod ring;
+pub mod text;
+pub mod utf8;
\ No newline at end of file
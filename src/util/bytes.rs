@@ -0,0 +1,22 @@
+/// Reads a `T` from `bytes` at `offset`, `None` if it doesn't fit.
+/// `T` must be a POD, the read doesn't need to be aligned.
+pub fn read<T: Copy>(bytes: &[u8], offset: usize) -> Option<T> {
+    let end = offset.checked_add(core::mem::size_of::<T>())?;
+    if end > bytes.len() {
+        return None;
+    }
+    // SAFETY: the range was checked above, the read is unaligned
+    Some(unsafe { (bytes.as_ptr().add(offset) as *const T).read_unaligned() })
+}
+
+/// Interprets `bytes` as a NUL terminated string.
+/// A missing terminator is tolerated, invalid UTF-8 is not.
+pub fn read_cstr(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// Whether all bytes sum up to zero, as ACPI and Multiboot checksums require
+pub fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)) == 0
+}
@@ -0,0 +1,88 @@
+/// A fixed capacity FIFO queue which doesn't allocate.
+/// Elements are pushed at the back and popped from the front.
+pub struct RingBuffer<T: Copy, const N: usize> {
+    buff: [Option<T>; N],
+    /// index of the oldest element
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        RingBuffer {
+            buff: [None; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of elements the buffer can hold
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Appends `val`, or returns it if the buffer is full
+    pub fn push(&mut self, val: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(val);
+        }
+        self.buff[(self.head + self.len) % N] = Some(val);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Appends `val`, discarding and returning the oldest element if the buffer is full
+    pub fn push_overwrite(&mut self, val: T) -> Option<T> {
+        let discarded = if self.is_full() { self.pop() } else { None };
+        let _ = self.push(val);
+        discarded
+    }
+
+    /// Removes and returns the oldest element
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let val = self.buff[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        val
+    }
+
+    /// The `index`th oldest element
+    pub fn get(&self, index: usize) -> Option<T> {
+        if index < self.len {
+            self.buff[(self.head + index) % N]
+        } else {
+            None
+        }
+    }
+
+    /// Removes all elements
+    pub fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+
+    /// Iterates from the oldest to the newest element
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).filter_map(move |i| self.get(i))
+    }
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -13,3 +13,15 @@ where
     use core::fmt::Write;
     FakeWriter { functor: apply }.write_fmt(args)
 }
+
+/// `s` cut off at a character boundary to fit `len` bytes
+pub fn truncate(s: &str, len: usize) -> &str {
+    if s.len() <= len {
+        return s;
+    }
+    let end = (0..=len)
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0);
+    &s[..end]
+}
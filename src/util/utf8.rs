@@ -0,0 +1,74 @@
+/// A streaming UTF-8 decoder, for text arriving byte by byte.
+/// Invalid or truncated sequences, overlong encodings and surrogates are
+/// reported as `None`, after which decoding continues with the next sequence.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Decoder {
+    code_point: u32,
+    /// continuation bytes still missing
+    needed: u8,
+    /// smallest code point the current sequence may encode
+    min: u32,
+}
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder {
+            code_point: 0,
+            needed: 0,
+            min: 0,
+        }
+    }
+
+    /// Whether a sequence was started but isn't complete yet
+    pub fn is_pending(&self) -> bool {
+        self.needed > 0
+    }
+
+    /// Feeds `byte`, calling `emit` with every character completed by it.
+    /// A byte which breaks a pending sequence emits `None` and starts anew.
+    pub fn push<F: FnMut(Option<char>)>(&mut self, byte: u8, mut emit: F) {
+        if self.needed > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.code_point = self.code_point << 6 | (byte & 0x3F) as u32;
+                self.needed -= 1;
+                if self.needed == 0 {
+                    emit(if self.code_point < self.min {
+                        None
+                    } else {
+                        char::from_u32(self.code_point)
+                    });
+                }
+                return;
+            }
+            self.needed = 0;
+            emit(None);
+        }
+        let (needed, min, bits) = match byte {
+            0x00..=0x7F => return emit(Some(byte as char)),
+            0xC0..=0xDF => (1, 0x80, byte & 0x1F),
+            0xE0..=0xEF => (2, 0x800, byte & 0x0F),
+            0xF0..=0xF7 => (3, 0x10000, byte & 0x07),
+            _ => return emit(None),
+        };
+        self.needed = needed;
+        self.min = min;
+        self.code_point = bits as u32;
+    }
+
+    /// Ends the input, emitting `None` for an incomplete sequence
+    pub fn finish<F: FnMut(Option<char>)>(&mut self, mut emit: F) {
+        if self.needed > 0 {
+            self.needed = 0;
+            emit(None);
+        }
+    }
+}
+
+/// Decodes `bytes`, yielding `None` for every invalid sequence
+pub fn decode<F: FnMut(Option<char>)>(bytes: &[u8], mut emit: F) {
+    let mut decoder = Decoder::new();
+    for &b in bytes {
+        decoder.push(b, &mut emit);
+    }
+    decoder.finish(emit);
+}